@@ -0,0 +1,129 @@
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use statrs::distribution::{ChiSquared, ContinuousCDF, Normal};
+use crate::dices::DiceModel;
+
+/// Significance level of the fairness tests
+const SIGNIFICANCE: f64 = 0.05;
+
+/// Fewest rolled dices per face for the chi-square test to be reliable
+const MIN_EXPECTED: f64 = 5.0;
+
+/// Log of physically thrown dices of a dice set, one throw per line with the newly thrown dices
+/// written as in the input, e.g. 1346
+pub struct RollLog {
+    path_name: String,
+    buf_writer: BufWriter<File>,
+}
+
+impl RollLog {
+    pub fn open(path: &str, dice_set: &str) -> Result<RollLog, String> {
+        let path_name = roll_log_name(path, dice_set);
+        let buf_writer = match OpenOptions::new().create(true).append(true).open(&path_name) {
+            Ok(f) => BufWriter::new(f),
+            Err(e) => return Err(format!("Error while open/create file {}: {}", path_name, e)),
+        };
+
+        Ok(RollLog { path_name, buf_writer })
+    }
+
+    /// Appends a throw, flushed right away so that an interrupted game keeps its throws
+    pub fn log(&mut self, dices: &[u8]) -> Result<(), String> {
+        if dices.is_empty() {
+            return Ok(());
+        }
+
+        let row = dices.iter().map(|d| d.to_string()).collect::<String>();
+        if let Err(e) = writeln!(self.buf_writer, "{}", row).and_then(|_| self.buf_writer.flush()) {
+            return Err(format!("Error while writing to file {}: {}", self.path_name, e));
+        }
+        Ok(())
+    }
+}
+
+fn roll_log_name(path: &str, dice_set: &str) -> String {
+    format!("{}/rolls.{}.log", path, dice_set)
+}
+
+/// Tests whether the dices in a roll log look fair, by a chi-square test over all faces and by
+/// confidence intervals per face, and optionally writes the observed face probabilities to a
+/// dice file for learning
+pub fn analyse_dice(path: &str, dice_set: &str, output: Option<&str>) -> Result<(), String> {
+    let counts = load_face_counts(path, dice_set)?;
+    let n: u64 = counts.iter().sum();
+    if n == 0 {
+        return Err(format!("Error, no rolls logged for dice set {}", dice_set));
+    }
+
+    let expected = n as f64 / 6.0;
+    let chi_square: f64 = counts.iter().map(|&c| (c as f64 - expected).powi(2) / expected).sum();
+    let p_value = ChiSquared::new(5.0).unwrap().sf(chi_square);
+
+    // Intervals are adjusted for the six faces tested together (Bonferroni), so all six hold
+    // jointly at the significance level
+    let z = Normal::new(0.0, 1.0).unwrap().inverse_cdf(1.0 - SIGNIFICANCE / 12.0);
+    println!("Analysing {} dices rolled with dice set {}", n, dice_set);
+    println!("Face  Count  Share   {:.0}% simultaneous interval (Bonferroni)", 100.0 * (1.0 - SIGNIFICANCE));
+    let mut faces_off: Vec<u8> = Vec::new();
+    for (i, &c) in counts.iter().enumerate() {
+        let (low, high) = wilson_interval(c, n, z);
+        let off = !(low..=high).contains(&(1.0 / 6.0));
+        if off {
+            faces_off.push(i as u8 + 1);
+        }
+        println!("{:4} {:6} {:6.4}  [{:.4}, {:.4}]{}", i + 1, c, c as f64 / n as f64, low, high, if off {" *"} else {""});
+    }
+    println!("Chi-square: {:.3} with 5 degrees of freedom, p-value: {:.4}", chi_square, p_value);
+
+    if expected < MIN_EXPECTED {
+        println!("Too few rolls to tell, log at least {} dices", (6.0 * MIN_EXPECTED) as u64);
+    } else if p_value < SIGNIFICANCE || !faces_off.is_empty() {
+        println!("Dices do not look fair at the {}% level, faces off: {:?}", 100.0 * SIGNIFICANCE, faces_off);
+    } else {
+        println!("Dices look fair at the {}% level", 100.0 * SIGNIFICANCE);
+    }
+
+    if let Some(path_name) = output {
+        DiceModel::from_counts(dice_set, &counts)?.save_file(path_name)?;
+        println!("Face probabilities written to {}", path_name);
+    }
+
+    Ok(())
+}
+
+fn load_face_counts(path: &str, dice_set: &str) -> Result<[u64; 6], String> {
+    let path_name = &roll_log_name(path, dice_set);
+    let buf_reader = match File::open(path_name) {
+        Ok(f) => BufReader::new(f),
+        Err(e) => return Err(format!("Error while open file {}: {}", path_name, e)),
+    };
+
+    let mut counts = [0u64; 6];
+    for line in buf_reader.lines() {
+        let line = match line {
+            Ok(l) => l,
+            Err(e) => return Err(format!("Error while reading from file {}: {}", path_name, e)),
+        };
+
+        for c in line.trim().chars() {
+            match c.to_digit(10) {
+                Some(d) if (1..7).contains(&d) => counts[d as usize - 1] += 1,
+                _ => return Err(format!("Error, file {} contains illegal dice: [{}]", path_name, c)),
+            }
+        }
+    }
+
+    Ok(counts)
+}
+
+/// Wilson score interval of the share of a face
+fn wilson_interval(count: u64, n: u64, z: f64) -> (f64, f64) {
+    let n = n as f64;
+    let share = count as f64 / n;
+    let z2 = z * z;
+
+    let center = (share + z2 / (2.0 * n)) / (1.0 + z2 / n);
+    let margin = z / (1.0 + z2 / n) * (share * (1.0 - share) / n + z2 / (4.0 * n * n)).sqrt();
+
+    (center - margin, center + margin)
+}
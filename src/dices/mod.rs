@@ -1,35 +1,268 @@
-use rand::distributions::{Distribution, Uniform};
-use rand::rngs::ThreadRng;
+use std::collections::HashMap;
+use std::fmt::{self, Display, Formatter};
+use std::fs::{read_to_string, write, File};
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use rand::distributions::{Distribution, Uniform, WeightedIndex};
+use rand::rngs::StdRng;
 use rand::seq::SliceRandom;
-use crate::utils::base7_to_base10;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use crate::utils::{base10_to_base7, base7_to_base10, records_in_file};
 
-pub enum Throw {
-    First,
-    Second,
-    #[allow(dead_code)]
-    Third,
+/// Dice file giving the relative weight of every face, ones first, e.g.
+///
+/// ```toml
+/// name = "worn"
+/// probabilities = [0.15, 0.16, 0.17, 0.17, 0.17, 0.18]
+/// ```
+#[derive(Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+struct DiceFile {
+    name: String,
+    probabilities: Vec<f64>,
 }
 
+/// Probability of every face of the dices, ones first, used in all learning and simulation
+#[derive(Clone, PartialEq, Debug)]
+pub struct DiceModel {
+    name: String,
+    probabilities: [f64; 6],
+}
+
+impl DiceModel {
+    pub fn fair() -> DiceModel {
+        DiceModel {
+            name: String::from("fair"),
+            probabilities: [1.0 / 6.0; 6],
+        }
+    }
+
+    /// Dice model by name, from a dice file or from six comma separated face weights
+    pub fn from_arg(arg: &str) -> Result<DiceModel, String> {
+        if arg == "fair" {
+            return Ok(DiceModel::fair());
+        }
+        if Path::new(arg).is_file() {
+            return DiceModel::from_file(arg);
+        }
+
+        let weights = arg
+            .split(',')
+            .map(|w| w.trim().parse::<f64>())
+            .collect::<Result<Vec<f64>, _>>()
+            .map_err(|e| format!("Error, unable to parse dice probabilities {}: {}", arg, e))?;
+        DiceModel::from_weights("weighted", &weights)
+    }
+
+    pub fn from_file(path_name: &str) -> Result<DiceModel, String> {
+        let content = match read_to_string(path_name) {
+            Ok(c) => c,
+            Err(e) => return Err(format!("Error while reading from file {}: {}", path_name, e)),
+        };
+        let file: DiceFile = match toml::from_str(&content) {
+            Ok(f) => f,
+            Err(e) => return Err(format!("Error while parsing dice file {}: {}", path_name, e)),
+        };
+
+        DiceModel::from_weights(&file.name, &file.probabilities)
+    }
+
+    /// Dice model from the number of times each face is thrown
+    pub fn from_counts(name: &str, counts: &[u64; 6]) -> Result<DiceModel, String> {
+        DiceModel::from_weights(name, &counts.map(|c| c as f64))
+    }
+
+    /// Writes the dice model to a dice file
+    pub fn save_file(&self, path_name: &str) -> Result<(), String> {
+        let file = DiceFile { name: self.name.clone(), probabilities: self.probabilities.to_vec() };
+        let content = toml::to_string(&file).map_err(|e| format!("Error while writing to file {}: {}", path_name, e))?;
+
+        if let Err(e) = write(path_name, content) {
+            return Err(format!("Error while writing to file {}: {}", path_name, e));
+        }
+        Ok(())
+    }
+
+    /// Dice model from relative face weights, normalised to probabilities
+    fn from_weights(name: &str, weights: &[f64]) -> Result<DiceModel, String> {
+        if weights.len() != 6 {
+            return Err(format!("Error, dice model {} must give a probability for each of the 6 faces", name));
+        }
+        if weights.iter().any(|w| !w.is_finite() || *w < 0.0) || weights.iter().sum::<f64>() <= 0.0 {
+            return Err(format!("Error, dice model {} must have non-negative probabilities that are not all zero", name));
+        }
+
+        let total: f64 = weights.iter().sum();
+        let mut probabilities = [0f64; 6];
+        probabilities.iter_mut().zip(weights).for_each(|(p, w)| *p = w / total);
+
+        Ok(DiceModel { name: String::from(name), probabilities })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn is_fair(&self) -> bool {
+        self.probabilities == DiceModel::fair().probabilities
+    }
+
+    /// Probability of throwing a face, one based
+    pub fn probability(&self, face: u8) -> f64 {
+        self.probabilities[face as usize - 1]
+    }
+
+    /// Records the dice model models in a folder are learned with
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        let path_name = &format!("{}/dices.bin", path);
+        let mut buf_writer = match File::create(path_name) {
+            Ok(f) => BufWriter::new(f),
+            Err(e) => return Err(format!("Error while open/create file {}: {}", path_name, e)),
+        };
+
+        let mut buf: Vec<u8> = (self.probabilities.len() as u64).to_le_bytes().to_vec();
+        self.probabilities.iter().for_each(|p| buf.extend(p.to_le_bytes()));
+        if let Err(e) = buf_writer.write_all(&buf).and_then(|_| buf_writer.flush()) {
+            return Err(format!("Error while writing to file {}: {}", path_name, e));
+        }
+        Ok(())
+    }
+
+    /// Checks that models in a folder are learned with this dice model, folders without a
+    /// recorded dice model are not checked
+    pub fn check(&self, path: &str) -> Result<(), String> {
+        let path_name = &format!("{}/dices.bin", path);
+        let mut buf_reader = match File::open(path_name) {
+            Ok(f) => BufReader::new(f),
+            Err(_) => return Ok(()),
+        };
+
+        let n_records = records_in_file(&mut buf_reader, path_name)?;
+        let mut buf = [0u8; 8];
+        let mut probabilities: Vec<f64> = Vec::with_capacity(n_records as usize);
+        for _ in 0..n_records {
+            if let Err(e) = buf_reader.read_exact(&mut buf) {
+                return Err(format!("Error while reading from file {}: {}", path_name, e));
+            }
+            probabilities.push(f64::from_le_bytes(buf));
+        }
+
+        if probabilities != self.probabilities {
+            return Err(format!("Error, models in {} are learned with dice probabilities {:?}, not {:?}",
+                               path, probabilities, self.probabilities));
+        }
+        Ok(())
+    }
+}
+
+impl Display for DiceModel {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let probabilities = self.probabilities.iter().map(|p| format!("{:.4}", p)).collect::<Vec<String>>();
+        write!(f, "{} [{}]", self.name, probabilities.join(", "))
+    }
+}
+
+/// Sampling of a single die, fair dices keep the uniform sampling so that seeded runs stay the same
+#[derive(Clone)]
+enum Die {
+    Fair(Uniform<u8>),
+    Weighted(WeightedIndex<f64>),
+}
+
+/// Position in the dice sequence shared by paired dices, every roll of every turn has its own
+/// seeded stream so that different strategies see the same dices however they hold
+#[derive(Clone)]
+struct Pairing {
+    seed: u64,
+    turn: u64,
+    roll: u64,
+}
+
+#[derive(Clone)]
 pub struct Dices {
-    rng: ThreadRng,
-    die: Uniform<u8>,
+    rng: StdRng,
+    die: Die,
     n_holds: Uniform<usize>,
     n_dies: usize,
+    pairing: Option<Pairing>,
 }
 
 impl Dices {
-    pub fn new() -> Self {
+    pub fn new(n_dies: usize, model: &DiceModel) -> Self {
+        Self::with_rng(StdRng::from_entropy(), n_dies, model)
+    }
+
+    /// Creates dices with a deterministic random stream if a seed is given, otherwise
+    /// falls back to entropy. Each worker should use its own stream number.
+    pub fn from_seed(n_dies: usize, model: &DiceModel, seed: Option<u64>, stream: u64) -> Self {
+        match seed {
+            Some(s) => Self::with_rng(StdRng::seed_from_u64(s ^ stream.wrapping_mul(0x9E37_79B9_7F4A_7C15)), n_dies, model),
+            None => Self::new(n_dies, model),
+        }
+    }
+
+    /// Creates dices replaying the same sequence of throws, turn by turn, for every strategy
+    /// compared on them, see next_turn and replay
+    pub fn paired(n_dies: usize, model: &DiceModel, seed: Option<u64>, stream: u64) -> Self {
+        let mut dices = Self::from_seed(n_dies, model, seed, stream);
+        let seed = dices.rng.gen();
+        dices.pairing = Some(Pairing { seed, turn: 0, roll: 0 });
+        dices
+    }
+
+    fn with_rng(rng: StdRng, n_dies: usize, model: &DiceModel) -> Self {
+        let die = if model.is_fair() {
+            Die::Fair(Uniform::from(1..7))
+        } else {
+            Die::Weighted(WeightedIndex::new(model.probabilities).expect("dice model should have valid probabilities"))
+        };
+
         Dices {
-            rng: rand::thread_rng(),
-            die: Uniform::from(1..7),
-            n_holds: Uniform::from(0..6),
-            n_dies: 5,
+            rng,
+            die,
+            n_holds: Uniform::from(0..n_dies + 1),
+            n_dies,
+            pairing: None,
+        }
+    }
+
+    /// Moves paired dices on to the throws of the next turn
+    pub fn next_turn(&mut self) {
+        if let Some(pairing) = &mut self.pairing {
+            pairing.turn += 1;
+            pairing.roll = 0;
+        }
+    }
+
+    /// Rewinds paired dices to the first turn, for the next strategy to play the same throws
+    pub fn replay(&mut self) {
+        if let Some(pairing) = &mut self.pairing {
+            pairing.turn = 0;
+            pairing.roll = 0;
+        }
+    }
+
+    /// Leaves the shared sequence for fresh throws, e.g. to check the best of the compared
+    /// strategies on dices it was not picked on
+    pub fn unpair(&mut self) {
+        if let Some(pairing) = self.pairing.take() {
+            self.rng = StdRng::seed_from_u64(!pairing.seed);
         }
     }
 
     fn throw(&mut self, n_dies: usize) -> Vec<u8> {
+        if let Some(pairing) = &mut self.pairing {
+            let position = (pairing.turn << 16) | pairing.roll;
+            self.rng = StdRng::seed_from_u64(pairing.seed ^ position.wrapping_mul(0x9E37_79B9_7F4A_7C15));
+            pairing.roll += 1;
+        }
+
         let res: Vec<u8> = (0..n_dies)
-            .map(|_| self.die.sample(&mut self.rng))
+            .map(|_| match &self.die {
+                Die::Fair(d) => d.sample(&mut self.rng),
+                Die::Weighted(d) => d.sample(&mut self.rng) as u8 + 1,
+            })
             .collect();
         res
     }
@@ -67,23 +300,208 @@ impl Dices {
         selected
     }
 
-    pub fn play_round(&mut self) -> (u16, u16, u8, u16, u16, u8, Vec<u8>) {
-        let throw1 = self.throw_and_hold(None);
-        let t1_code = base7_to_base10(&throw1);
+    /// Plays a turn of random holds, returns thrown dices code, hold code and hold length for
+    /// every throw followed by a hold together with the dices of the final throw
+    pub fn play_round(&mut self, rolls: usize) -> (Vec<(u32, u32, u8)>, Vec<u8>) {
+        let mut holds: Vec<(u32, u32, u8)> = Vec::with_capacity(rolls - 1);
+        let mut thrown = self.throw_and_hold(None);
+
+        for _ in 1..rolls {
+            let t_code = base7_to_base10(&thrown);
+            let selected = self.select(thrown);
+            holds.push((t_code, base7_to_base10(&selected), selected.len() as u8));
+
+            thrown = self.throw_and_hold(Some(selected));
+        }
+
+        (holds, thrown)
+    }
+
+    /// Finishes a turn from a given hold with random holds for the holds left, returns the dices
+    /// of the final throw
+    pub fn finish_round(&mut self, hold: Vec<u8>, holds_left: usize) -> Vec<u8> {
+        let mut thrown = self.throw_and_hold(Some(hold));
+
+        for _ in 0..holds_left {
+            let selected = self.select(thrown);
+            thrown = self.throw_and_hold(Some(selected));
+        }
+
+        thrown
+    }
+}
+
+/// All distinct sorted throws of n_dies dices
+pub fn sorted_throws(n_dies: usize) -> Vec<Vec<u8>> {
+    let mut res: Vec<Vec<u8>> = vec![Vec::new()];
+
+    for _ in 0..n_dies {
+        let mut next: Vec<Vec<u8>> = Vec::new();
+        for t in res {
+            let from = *t.last().unwrap_or(&1);
+            for d in from..7 {
+                let mut n = t.clone();
+                n.push(d);
+                next.push(n);
+            }
+        }
+        res = next;
+    }
+
+    res
+}
+
+/// All distinct sorted holds that can be selected from a sorted throw, shortest first
+pub fn distinct_holds(dices: &Vec<u8>) -> Vec<Vec<u8>> {
+    let mut res: Vec<Vec<u8>> = vec![Vec::new()];
+
+    for d in dices {
+        let mut next: Vec<Vec<u8>> = Vec::new();
+        for h in &res {
+            let mut n = h.clone();
+            n.push(*d);
+            if !res.contains(&n) && !next.contains(&n) {
+                next.push(n);
+            }
+        }
+        res.extend(next);
+    }
+
+    res.sort_by_key(|h| h.len());
+    res
+}
+
+/// All distinct sorted throws of the dices and all holds between them, with the distribution of
+/// the next throw for every hold, indexed for expected value calculations over a turn
+pub struct Transitions {
+    pub throws: Vec<Vec<u8>>,
+    pub throw_index: HashMap<u32, usize>,
+    /// Next throw and its probability for every hold
+    pub holds: Vec<Vec<(usize, f64)>>,
+    /// Distinct holds of every throw
+    pub throw_holds: Vec<Vec<usize>>,
+    pub hold_codes: Vec<u32>,
+}
+
+impl Transitions {
+    pub fn new(n_dies: usize, model: &DiceModel) -> Transitions {
+        let throws = sorted_throws(n_dies);
+        let throw_index: HashMap<u32, usize> = throws
+            .iter()
+            .enumerate()
+            .map(|(i, t)| (base7_to_base10(t), i))
+            .collect();
+
+        let mut holds: Vec<Vec<(usize, f64)>> = Vec::new();
+        let mut hold_codes: Vec<u32> = Vec::new();
+        for n in 0..=n_dies {
+            let outcomes = throw_outcomes(n_dies - n, model);
+            for hold in sorted_throws(n) {
+                let transitions = outcomes
+                    .iter()
+                    .map(|(o, p)| {
+                        let mut dices = hold.clone();
+                        dices.extend(o);
+                        dices.sort();
+                        (*throw_index.get(&base7_to_base10(&dices)).unwrap(), *p)
+                    })
+                    .collect::<Vec<(usize, f64)>>();
+
+                hold_codes.push(base7_to_base10(&hold));
+                holds.push(transitions);
+            }
+        }
+
+        let throw_holds = throws
+            .iter()
+            .map(|t| distinct_holds(t)
+                .iter()
+                .map(|h| hold_codes.iter().position(|&c| c == base7_to_base10(h)).unwrap())
+                .collect::<Vec<usize>>())
+            .collect::<Vec<Vec<usize>>>();
+
+        Transitions { throws, throw_index, holds, throw_holds, hold_codes }
+    }
+
+    /// Expected value of every hold given the value of every throw
+    pub fn hold_values(&self, throw_values: &[f64]) -> Vec<f64> {
+        self.holds
+            .iter()
+            .map(|t| t.iter().map(|&(d, p)| p * throw_values[d]).sum::<f64>())
+            .collect()
+    }
 
-        let selected1 = self.select(throw1);
-        let s1_code = base7_to_base10(&selected1);
-        let s1_len = selected1.len() as u8;
+    /// Best hold of a throw, ties keep the hold with fewer dices
+    pub fn best_hold(&self, throw_idx: usize, hold_values: &[f64]) -> (usize, f64) {
+        let mut best = (0usize, f64::MIN);
+        for &h in &self.throw_holds[throw_idx] {
+            if hold_values[h] > best.1 + 1e-9 {
+                best = (h, hold_values[h]);
+            }
+        }
 
-        let throw2 = self.throw_and_hold(Some(selected1));
-        let t2_code = base7_to_base10(&throw2);
+        best
+    }
 
-        let selected2 = self.select(throw2);
-        let s2_code = base7_to_base10(&selected2);
-        let s2_len = selected2.len() as u8;
+    /// Hold values for every throw followed by a hold in a turn of rolls, given the value of
+    /// every final throw, together with the expected value of the turn
+    pub fn turn_holds(&self, mut throw_values: Vec<f64>, rolls: usize) -> (Vec<Vec<f64>>, f64) {
+        // Work backwards from the last throw
+        let mut holds: Vec<Vec<f64>> = Vec::with_capacity(rolls - 1);
+        for _ in 1..rolls {
+            let hold_values = self.hold_values(&throw_values);
+            throw_values = (0..self.throws.len())
+                .map(|d| self.best_hold(d, &hold_values).1)
+                .collect::<Vec<f64>>();
+            holds.push(hold_values);
+        }
+        holds.reverse();
+        let value = self.holds[0].iter().map(|&(d, p)| p * throw_values[d]).sum::<f64>();
 
-        let throw3 = self.throw_and_hold(Some(selected2));
+        (holds, value)
+    }
 
-        (t1_code, s1_code, s1_len, t2_code, s2_code, s2_len, throw3)
+    pub fn hold(&self, hold_idx: usize) -> Vec<u8> {
+        base10_to_base7(self.hold_codes[hold_idx])
     }
 }
+
+/// Every distinct sorted throw of n_dies dices paired with every distinct hold of at most
+/// max_holds dices from it
+pub fn throw_hold_pairs(n_dies: usize, max_holds: u8) -> Vec<(Vec<u8>, Vec<u8>)> {
+    sorted_throws(n_dies)
+        .into_iter()
+        .flat_map(|t| {
+            distinct_holds(&t)
+                .into_iter()
+                .filter(|h| h.len() as u8 <= max_holds)
+                .map(|h| (t.clone(), h))
+                .collect::<Vec<(Vec<u8>, Vec<u8>)>>()
+        })
+        .collect()
+}
+
+/// All distinct sorted outcomes of throwing n_dies dices together with their probability
+pub fn throw_outcomes(n_dies: usize, model: &DiceModel) -> Vec<(Vec<u8>, f64)> {
+    let total = 6f64.powi(n_dies as i32);
+
+    sorted_throws(n_dies)
+        .into_iter()
+        .map(|t| {
+            let mut perms = factorial(t.len());
+            for face in 1..7u8 {
+                perms /= factorial(t.iter().filter(|&&d| d == face).count());
+            }
+            let probability = if model.is_fair() {
+                perms as f64 / total
+            } else {
+                perms as f64 * t.iter().map(|&d| model.probability(d)).product::<f64>()
+            };
+            (t, probability)
+        })
+        .collect()
+}
+
+fn factorial(n: usize) -> u64 {
+    (1..=n as u64).product()
+}
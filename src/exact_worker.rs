@@ -0,0 +1,144 @@
+use std::collections::HashMap;
+use crate::dices::{distinct_holds, sorted_throws, throw_outcomes};
+use crate::score_box::rules::*;
+use crate::score_box::ruleset::Ruleset;
+use crate::hand_worker::load_hands;
+use crate::utils::{base10_to_base7, base7_to_base10, thread_pool};
+
+pub fn learn_exact_hands(path: &str, rule: Option<usize>, rules: &Ruleset) -> Result<(), String> {
+    let pool = thread_pool()?;
+
+    pool.in_place_scope(|s| {
+        for hand in rules.hands().into_iter().filter(|h| rule.is_none_or(|r| r == h.id())) {
+            s.spawn(move |_| run(hand, path));
+        }
+    });
+
+    Ok(())
+}
+
+fn run(mut hand: Hand, path: &str) {
+    println!("Exact learning {}", hand.name());
+
+    exact_optimal_holds(&mut hand);
+
+    if let Err(e) = hand.save_optimal_holds(path) {
+        println!("Could not save \"{}\n optimal holds, error: {}", hand.name(), e);
+    }
+}
+
+/// Calculates the exact expected score for every throw and hold of a hand and stores the best
+/// hold per throw, working backwards from the final throw. Returns, per throw followed by a hold,
+/// the expected score of the dices thrown after the hold given the best play from then on.
+pub fn exact_optimal_holds(hand: &mut Hand) -> Vec<HashMap<u32, f64>> {
+    let n_dies = hand.n_dices();
+    let min_holds = hand.min_holds();
+    let throws = sorted_throws(n_dies);
+    let outcomes = all_outcomes(hand);
+
+    // Expected score of a throw given the best play from that point on
+    let mut values: HashMap<u32, f64> = throws
+        .iter()
+        .map(|t| (base7_to_base10(t), hand.score(t) as f64))
+        .collect();
+
+    let opt_holds = hand.optimal_holds_mut();
+    let mut next_values: Vec<HashMap<u32, f64>> = vec![HashMap::new(); opt_holds.len()];
+    for throw in (0..opt_holds.len()).rev() {
+        opt_holds[throw].clear();
+
+        for thrown in &throws {
+            let mut best: Option<(u8, u32, f64)> = None;
+
+            for hold in distinct_holds(thrown).into_iter().filter(|h| h.len() as u8 <= min_holds) {
+                let value = hold_value(&hold, &outcomes, &values);
+
+                // Holds come shortest first, so a tie keeps the hold with fewer dices
+                if best.is_none_or(|(_, _, v)| value > v + 1e-12) {
+                    best = Some((hold.len() as u8, base7_to_base10(&hold), value));
+                }
+            }
+
+            if let Some(b) = best {
+                opt_holds[throw].insert(base7_to_base10(thrown), b);
+            }
+        }
+
+        next_values[throw] = values;
+        values = opt_holds[throw].iter().map(|(&t, &(_, _, v))| (t, v)).collect();
+    }
+
+    next_values
+}
+
+/// Outcomes of throwing any number of dices up to all dices of the hand
+fn all_outcomes(hand: &Hand) -> Vec<Vec<(Vec<u8>, f64)>> {
+    (0..=hand.n_dices()).map(|n| throw_outcomes(n, hand.dice_model())).collect()
+}
+
+/// Expected score of a hold given the expected score of the dices thrown after it
+fn hold_value(hold: &[u8], outcomes: &[Vec<(Vec<u8>, f64)>], values: &HashMap<u32, f64>) -> f64 {
+    let n_dies = outcomes.len() - 1;
+
+    outcomes[n_dies - hold.len()]
+        .iter()
+        .map(|(o, p)| {
+            let mut dices = hold.to_vec();
+            dices.extend(o);
+            dices.sort();
+            p * values.get(&base7_to_base10(&dices)).unwrap()
+        })
+        .sum::<f64>()
+}
+
+/// Compares the optimal holds in learned hand models with the exactly calculated ones and
+/// reports per hand and throw the throws holding wrong, their loss in expected score and the
+/// worst offenders
+pub fn validate_hands(path: &str, rule: Option<usize>, worst: usize, rules: &Ruleset) -> Result<(), String> {
+    let hands = load_hands(path, rules, false)?;
+    if hands.is_empty() {
+        return Err(String::from("Error, no hand models to validate"));
+    }
+
+    for learned in hands.iter().filter(|h| rule.is_none_or(|r| r == h.id())) {
+        let mut exact = Hand::new(rules.boxes()[learned.id()].clone(), learned.id(), rules.n_dices(), learned.rolls(), rules.dice_model());
+        let next_values = exact_optimal_holds(&mut exact);
+        let outcomes = all_outcomes(&exact);
+
+        // Loss, throw, thrown dices, learned hold and exact hold of throws holding wrong
+        let mut offenders: Vec<(f64, usize, u32, u32, u32)> = Vec::new();
+        for (throw, values) in next_values.iter().enumerate() {
+            let exact_holds = exact.optimal_holds(throw)?;
+            let learned_holds = learned.optimal_holds(throw)?;
+
+            let mut wrong = 0;
+            let mut missing = 0;
+            let mut loss = 0.0;
+            for (thrown, (_, best_hold, best_value)) in exact_holds {
+                match learned_holds.get(thrown) {
+                    Some((_, hold, _)) => {
+                        let value = hold_value(&base10_to_base7(*hold), &outcomes, values);
+                        if best_value - value > 1e-9 {
+                            wrong += 1;
+                            loss += best_value - value;
+                            offenders.push((best_value - value, throw, *thrown, *hold, *best_hold));
+                        }
+                    }
+                    None => missing += 1,
+                }
+            }
+
+            println!("{} throw {}: {} of {} throws hold wrong, {} missing, expected score loss {:.4} in total and {:.4} at most",
+                     learned.name(), throw + 1, wrong, exact_holds.len(), missing, loss,
+                     offenders.iter().filter(|o| o.1 == throw).map(|o| o.0).fold(0.0, f64::max));
+        }
+
+        offenders.sort_by(|a, b| b.0.total_cmp(&a.0));
+        for (loss, throw, thrown, hold, best_hold) in offenders.iter().take(worst) {
+            println!("    throw {}: {:?} holds {:?} instead of {:?}, loss {:.4}",
+                     throw + 1, base10_to_base7(*thrown), base10_to_base7(*hold), base10_to_base7(*best_hold), loss);
+        }
+    }
+
+    Ok(())
+}
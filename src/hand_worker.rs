@@ -1,61 +1,169 @@
-use crate::dices::Dices;
-use crate::dices::Throw::{First, Second};
+use std::collections::HashMap;
+use std::path::Path;
+use statrs::distribution::{ContinuousCDF, Normal};
+use crate::dices::{sorted_throws, throw_hold_pairs, Dices};
 use crate::score_box::rules::*;
+use crate::score_box::ruleset::Ruleset;
 use crate::score_box::MCHands;
-use crate::utils::thread_pool;
-use crate::score_box::rules::HandType::*;
+use crate::utils::{base7_to_base10, thread_pool};
+
+/// Fewest plays of a throw and hold for its average score to be trusted
+const MIN_HITS: f64 = 30.0;
+
+/// Laps between convergence checks when learning adaptively
+const ADAPTIVE_BATCH: i64 = 100_000;
+
+/// Difference in expected score, as share of the hand's highest score, below which two holds
+/// count as equally good when learning adaptively
+const INDIFFERENCE: f64 = 0.01;
+
+#[derive(Clone, Copy)]
 pub enum LearnMode {
-    Skip,
     Learn,
     Debug,
 }
 
-pub fn learn_hands(laps: i64, path: &str, rule: Option<usize>, full: bool) -> Result<(), String> {
+/// How holds are picked when learning hands
+#[derive(Clone, Copy)]
+pub enum Sampling {
+    /// Random holds in every throw of a turn
+    Random,
+    /// Every distinct throw and hold in turn, round-robin over all throws of a turn, finishing the
+    /// turn with random holds
+    Systematic,
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn learn_hands(laps: i64, path: &str, rule: Option<usize>, full: bool, sampling: Sampling, resume: bool, confidence: Option<f64>, rules: &Ruleset, seed: Option<u64>) -> Result<(), String> {
     let pool = thread_pool()?;
-    let mut learn: [&LearnMode;15] = [&LearnMode::Skip;15];
-    if let Some(r) = rule {
-        learn[r] = if full {&LearnMode::Debug} else {&LearnMode::Learn};
-    } else {
-        learn = if full {[&LearnMode::Debug;15]} else {[&LearnMode::Learn;15]};
-    }
+    let learn = if full {LearnMode::Debug} else {LearnMode::Learn};
+    let z = match confidence {
+        Some(c) if c > 0.5 && c < 1.0 => Some(Normal::new(0.0, 1.0).unwrap().inverse_cdf(c)),
+        Some(_) => return Err(String::from("Error, confidence must be above 0.5 and below 1")),
+        None => None,
+    };
 
     pool.in_place_scope(|s| {
-        s.spawn(move |_| run(Ones, laps, path, learn));
-        s.spawn(move |_| run(Twos, laps, path, learn));
-        s.spawn(move |_| run(Threes, laps, path, learn));
-        s.spawn(move |_| run(Fours, laps, path, learn));
-        s.spawn(move |_| run(Fives, laps, path, learn));
-        s.spawn(move |_| run(Sixes, laps, path, learn));
-        s.spawn(move |_| run(OnePair, laps, path, learn));
-        s.spawn(move |_| run(TwoPairs, laps, path, learn));
-        s.spawn(move |_| run(ThreeOfAKind, laps, path, learn));
-        s.spawn(move |_| run(FourOfAKind, laps, path, learn));
-        s.spawn(move |_| run(SmallStraight, laps, path, learn));
-        s.spawn(move |_| run(LargeStraight, laps, path, learn));
-        s.spawn(move |_| run(FullHouse, laps, path, learn));
-        s.spawn(move |_| run(Chance, laps, path, learn));
-        s.spawn(move |_| run(Yatzy, laps, path, learn));
+        for hand in rules.hands().into_iter().filter(|h| rule.is_none_or(|r| r == h.id())) {
+            s.spawn(move |_| run(hand, laps, path, learn, sampling, resume, z, seed));
+        }
     });
 
     Ok(())
 }
 
-fn run(hand_type: HandType, laps: i64, path: &str, learn: [&LearnMode;15]) {
-    if let LearnMode::Skip = learn[hand_type.id()] { return; }
+/// Learns a hand for a number of laps, or adaptively, given z, in batches of laps until the best
+/// hold of every throw is decided or the laps are used up
+#[allow(clippy::too_many_arguments)]
+fn run(mut hand: Hand, laps: i64, path: &str, learn: LearnMode, sampling: Sampling, resume: bool, z: Option<f64>, seed: Option<u64>) {
+    let mut dices = Dices::from_seed(hand.n_dices(), hand.dice_model(), seed, hand.id() as u64);
+    let mut mc = MCHands::new(hand.rolls());
+    let min_holds = hand.min_holds();
+    let pairs = throw_hold_pairs(hand.n_dices(), min_holds);
+
+    if resume && Path::new(&format!("{}/mchand.{}.bin", path, hand.name())).is_file() {
+        if let Err(e) = mc.load_scores(path, &hand.name()) {
+            println!("{}", e);
+            return;
+        }
+        println!("Continue learning {}", hand.name());
+    } else {
+        println!("Learning {}", hand.name());
+    }
+
+    match z {
+        Some(z) => {
+            let throws = sorted_throws(hand.n_dices()).len() * (hand.rolls() - 1);
+            let tolerance = INDIFFERENCE * hand.max_score() as f64;
+            let mut n_holds: HashMap<u32, usize> = HashMap::new();
+            pairs.iter().for_each(|(t, _)| *n_holds.entry(base7_to_base10(t)).or_insert(0) += 1);
+            let mut lap = 0;
+
+            while lap < laps {
+                let batch = ADAPTIVE_BATCH.min(laps - lap);
+                play_laps(&hand, &mut dices, &mut mc, sampling, &pairs, lap, batch);
+                lap += batch;
+
+                let undecided = throws - mc.decided_throws(&n_holds, min_holds, MIN_HITS, hand.max_score() as f64, z, tolerance);
+                println!("Learning {}, {} of {} throws undecided after {} laps", hand.name(), undecided, throws, lap);
+                if undecided == 0 {
+                    break;
+                }
+            }
+        }
+        None => play_laps(&hand, &mut dices, &mut mc, sampling, &pairs, 0, laps),
+    }
+
+    save_hand(&mut hand, &mc, &pairs, path);
+
+    if let LearnMode::Debug = learn {
+        if let Err(e) = mc.debug_scores(path, &hand.name()) {
+            println!("Could not export \"{}\n mc hand, error: {}", hand.name(), e);
+        }
+    }
+}
+
+/// Plays laps of a hand from a lap number on, the lap number keeps systematic sampling going
+/// round-robin between calls
+fn play_laps(hand: &Hand, dices: &mut Dices, mc: &mut MCHands, sampling: Sampling, pairs: &[(Vec<u8>, Vec<u8>)], from: i64, laps: i64) {
+    match sampling {
+        Sampling::Random => {
+            for _ in 0..laps {
+                let (holds, thrown) = dices.play_round(hand.rolls());
+                let score = hand.score(&thrown);
 
-    let mut dices = Dices::new();
-    let mut hand = Hand::new(hand_type);
-    let mut mc = MCHands::new();
-    println!("Learning {}", hand.name());
+                for (throw, (t_code, s_code, s_len)) in holds.into_iter().enumerate() {
+                    mc.update_scores(throw, t_code, s_code, s_len, score as f64);
+                }
+            }
+        }
+        Sampling::Systematic => {
+            let holds = hand.rolls() - 1;
+            let cycle = pairs.len() * holds;
 
-    for _ in 0..laps {
-        let (t1_code, s1_code, s1_len, t2_code, s2_code, s2_len, throw3) = dices.play_round();
-        let score = hand.score(&throw3);
+            for lap in from as usize..(from + laps) as usize {
+                let throw = lap % cycle / pairs.len();
+                let (thrown, hold) = &pairs[lap % pairs.len()];
+                let score = hand.score(&dices.finish_round(hold.clone(), holds - throw - 1));
 
-        mc.update_scores(First, t1_code, s1_code, s1_len, score as f64);
-        mc.update_scores(Second, t2_code, s2_code, s2_len, score as f64);
+                mc.update_scores(throw, base7_to_base10(thrown), base7_to_base10(hold), hold.len() as u8, score as f64);
+            }
+        }
     }
+}
+
+/// Merges the learned hits and total scores of hands in other model folders into the ones in
+/// path and saves the resulting optimal holds
+pub fn merge_hands(path: &str, from: &[String], rule: Option<usize>, rules: &Ruleset) -> Result<(), String> {
+    let mut from_paths: Vec<String> = Vec::new();
+    for f in from {
+        let from_path = rules.model_path(f)?;
+        rules.dice_model().check(&from_path)?;
+        from_paths.push(from_path);
+    }
+
+    for mut hand in rules.hands().into_iter().filter(|h| rule.is_none_or(|r| r == h.id())) {
+        let name = hand.name();
+        let mut mc = MCHands::new(hand.rolls());
+        if Path::new(&format!("{}/mchand.{}.bin", path, name)).is_file() {
+            mc.load_scores(path, &name)?;
+        }
+        for from_path in &from_paths {
+            mc.load_scores(from_path, &name)?;
+        }
 
+        println!("Merged {}", name);
+        let pairs = throw_hold_pairs(hand.n_dices(), hand.min_holds());
+        save_hand(&mut hand, &mc, &pairs, path);
+    }
+
+    Ok(())
+}
+
+/// Saves optimal holds from the learned hits and total scores, together with the hits and total
+/// scores themselves to continue from
+fn save_hand(hand: &mut Hand, mc: &MCHands, pairs: &[(Vec<u8>, Vec<u8>)], path: &str) {
+    report_hits(hand, mc, pairs);
     let min_holds = hand.min_holds();
     mc.update_optimal_holds(hand.optimal_holds_mut(), min_holds);
 
@@ -63,31 +171,31 @@ fn run(hand_type: HandType, laps: i64, path: &str, learn: [&LearnMode;15]) {
         println!("Could not save \"{}\n optimal holds, error: {}", hand.name(), e);
     }
 
-    if let LearnMode::Debug = learn[hand.id()] {
-        if let Err(e) = mc.debug_scores(path, &hand.name()) {
-            println!("Could not export \"{}\n mc hand, error: {}", hand.name(), e);
-        }
+    if let Err(e) = mc.save_scores(path, &hand.name()) {
+        println!("Could not save \"{}\n mc hand, error: {}", hand.name(), e);
+    }
+}
+
+/// Reports throws and holds played too few times for their average score to be trusted
+fn report_hits(hand: &Hand, mc: &MCHands, pairs: &[(Vec<u8>, Vec<u8>)]) {
+    let hits: Vec<f64> = (0..hand.rolls() - 1)
+        .flat_map(|throw| {
+            pairs
+                .iter()
+                .map(move |(t, h)| mc.hits(throw, base7_to_base10(t), base7_to_base10(h)))
+        })
+        .collect();
+    let few = hits.iter().filter(|&&h| h < MIN_HITS).count();
+    let fewest = hits.iter().copied().fold(f64::INFINITY, f64::min);
+
+    if few > 0 {
+        println!("Learned {}, {} of {} throws and holds played fewer than {} times, fewest {}",
+                 hand.name(), few, hits.len(), MIN_HITS, fewest);
     }
 }
 
-pub fn load_hands(path: &str, fail: bool) -> Result<Vec<Box<Hand>>, String> {
-    let mut res: Vec<Box<Hand>> = Vec::with_capacity(15);
-
-    res.push(Box::new(Hand::new(Ones)));
-    res.push(Box::new(Hand::new(Twos)));
-    res.push(Box::new(Hand::new(Threes)));
-    res.push(Box::new(Hand::new(Fours)));
-    res.push(Box::new(Hand::new(Fives)));
-    res.push(Box::new(Hand::new(Sixes)));
-    res.push(Box::new(Hand::new(OnePair)));
-    res.push(Box::new(Hand::new(TwoPairs)));
-    res.push(Box::new(Hand::new(ThreeOfAKind)));
-    res.push(Box::new(Hand::new(FourOfAKind)));
-    res.push(Box::new(Hand::new(SmallStraight)));
-    res.push(Box::new(Hand::new(LargeStraight)));
-    res.push(Box::new(Hand::new(FullHouse)));
-    res.push(Box::new(Hand::new(Chance)));
-    res.push(Box::new(Hand::new(Yatzy)));
+pub fn load_hands(path: &str, rules: &Ruleset, fail: bool) -> Result<Vec<Hand>, String> {
+    let mut res: Vec<Hand> = rules.hands();
 
     let mut index = res.len();
     while index > 0 {
@@ -5,14 +5,25 @@ mod utils;
 mod play_worker;
 mod distr_worker;
 mod weight_worker;
+mod exact_worker;
+mod optimal_worker;
+mod dice_worker;
+mod optimiser;
 
 use crate::hand_worker::load_hands;
 use clap::{Parser, Subcommand};
-use hand_worker::learn_hands;
+use hand_worker::{learn_hands, merge_hands, Sampling};
 use crate::distr_worker::{learn_hand_distributions, load_hand_distributions};
 use crate::weight_worker::{export_weights, load_weights, strategy_learn};
-use crate::play_worker::play_with_own_dices;
+use crate::play_worker::{play_with_own_dices, PlayPolicy};
 use crate::utils::check_path_create_folder;
+use crate::exact_worker::{learn_exact_hands, validate_hands};
+use crate::optimal_worker::{learn_optimal_strategy, simulate_optimal};
+use crate::weight_worker::simulate_weights;
+use crate::score_box::ruleset::Ruleset;
+use crate::dices::DiceModel;
+use crate::dice_worker::{analyse_dice, RollLog};
+use crate::optimiser::Optimiser;
 
 static EXPORT_DIR: &str = "export";
 static DEBUG_DIR: &str = "debug";
@@ -24,6 +35,49 @@ struct Cli {
     #[arg(short, long, value_name = "DIR")]
     path: String,
 
+    /// Seed for reproducible dices, each worker gets its own deterministic stream
+    #[arg(long, global = true, value_name = "SEED")]
+    seed: Option<u64>,
+
+    /// Ruleset to use, scandinavian (default), maxi, yahtzee or a ruleset file (TOML)
+    #[arg(long, global = true, value_name = "RULESET", default_value = "scandinavian")]
+    ruleset: String,
+
+    /// Upper section sum needed for the bonus, overrides the ruleset
+    #[arg(long, global = true, value_name = "SUM")]
+    bonus_threshold: Option<u16>,
+
+    /// Upper section bonus, overrides the ruleset
+    #[arg(long, global = true, value_name = "BONUS")]
+    bonus_amount: Option<u32>,
+
+    /// Number of rolls per turn
+    #[arg(long, global = true, value_name = "ROLLS")]
+    rolls: Option<usize>,
+
+    /// Forced order mode, hands must be scored top-down in score card order
+    #[arg(long, global = true)]
+    forced: bool,
+
+    /// MC player picks holds for all open hands jointly instead of for one target hand, weights
+    /// are learned separately for this
+    #[arg(long, global = true)]
+    joint_holds: bool,
+
+    /// MC player weighs in the odds of reaching the upper section bonus by the upper sum so far
+    /// when picking holds and hands, implies joint holds, weights are learned separately for this
+    #[arg(long, global = true)]
+    bonus_aware: bool,
+
+    /// MC player uses weights depending on the phase of the game, the number of hands left and
+    /// whether the upper section bonus is still to be won, weights are learned separately for this
+    #[arg(long, global = true)]
+    phased_weights: bool,
+
+    /// Dice face probabilities, fair (default), six comma separated weights ones first or a dice file (TOML)
+    #[arg(long, global = true, value_name = "DICES", default_value = "fair")]
+    dices: String,
+
     #[command(subcommand)]
     command: Commands,
 }
@@ -36,6 +90,27 @@ enum Commands {
         #[arg(short, value_name="LAPS")]
         scores: Option<i64>,
 
+        /// Sample every distinct throw and hold in turn when learning hands, instead of random holds
+        #[arg(long)]
+        systematic: bool,
+
+        /// Learn hands adaptively until the best hold of every throw is told apart from the
+        /// runner-up with this confidence (e.g. 0.95), -s then gives the most laps per hand
+        #[arg(long, value_name="CONFIDENCE", requires="scores")]
+        adaptive: Option<f64>,
+
+        /// Continue learning hands from the hits and scores saved by earlier runs, if any
+        #[arg(long)]
+        resume: bool,
+
+        /// Merge hits and scores of hands learned in other folders (same layout as --path)
+        #[arg(long, value_name="DIR", num_args(1..))]
+        merge: Option<Vec<String>>,
+
+        /// Learn models for yatzy hands by exact expected score calculation
+        #[arg(short, long)]
+        exact: bool,
+
         /// Choose specific yatzy hand to learn, leave value empty for all
         #[arg(short, value_name="HAND (zero based)")]
         rule: Option<usize>,
@@ -52,9 +127,32 @@ enum Commands {
         #[arg(short)]
         full: bool,
 
-        /// Bonus to use in game strategy learning
+        /// Optimiser for game strategy learning, grid (default), cma-es or genetic
+        #[arg(long, value_name="NAME", default_value="grid")]
+        optimiser: String,
+
+        /// Compare weights in game strategy learning on the same dices, so that differences come
+        /// from the strategy rather than luck
+        #[arg(long)]
+        paired: bool,
+
+        /// Race the weights tried in a grid batch by successive halving, the sub-laps are then the
+        /// games played by the last two, grid optimiser only
+        #[arg(long)]
+        racing: bool,
+
+        /// Rank weights in game strategy learning on the lower bound of their 95% confidence
+        /// interval instead of the average score
+        #[arg(long)]
+        lower_bound: bool,
+
+        /// Bonus to use in game strategy learning, defaults to the upper section bonus
         #[arg(short)]
         bonus: Option<u32>,
+
+        /// Solve the optimal game strategy over all score card states
+        #[arg(short, long)]
+        optimal: bool,
     },
 
     /// Export models to readable format
@@ -77,6 +175,44 @@ enum Commands {
         /// Human (own dices) vs MC
         #[arg(short, long, value_name="BONUS")]
         interactive: Option<u32>,
+
+        /// Simulate games with the MC player and report the average score
+        #[arg(short, long, value_name="LAPS")]
+        simulate: Option<u32>,
+
+        /// Bonus of weights to use in simulation
+        #[arg(short, long)]
+        bonus: Option<u32>,
+
+        /// Let the MC player use the optimal strategy instead of weights
+        #[arg(short, long)]
+        optimal: bool,
+
+        /// Dice set thrown in interactive play, every throw is logged for dice analysis
+        #[arg(long, value_name="NAME", default_value="default")]
+        dice_set: String,
+    },
+
+    /// Compare learned hand models with exactly calculated optimal holds
+    Validate {
+        /// Choose specific yatzy hand to validate, leave value empty for all
+        #[arg(short, value_name="HAND (zero based)")]
+        rule: Option<usize>,
+
+        /// Number of worst holds to list per hand
+        #[arg(short, long, value_name="COUNT", default_value_t = 10)]
+        worst: usize,
+    },
+
+    /// Analyse the fairness of physically thrown dices logged in interactive play
+    AnalyseDice {
+        /// Dice set to analyse
+        #[arg(long, value_name="NAME", default_value="default")]
+        dice_set: String,
+
+        /// Write the observed face probabilities to a dice file (TOML) usable with --dices
+        #[arg(short, long, value_name="FILE")]
+        output: Option<String>,
     },
 }
 
@@ -84,16 +220,36 @@ fn main() -> Result<(), String> {
     let args = Cli::parse();
 
     check_path_create_folder(&args.path, None)?;
+    let mut rules = Ruleset::from_arg(&args.ruleset)?;
+    rules.set_forced_order(args.forced);
+    rules.set_dice_model(DiceModel::from_arg(&args.dices)?);
+    if let Some(rolls) = args.rolls {
+        rules.set_rolls(rolls)?;
+    }
+    rules.set_bonus(
+        args.bonus_threshold.unwrap_or(rules.bonus_threshold()),
+        args.bonus_amount.unwrap_or(rules.bonus()),
+    );
+    let path = &rules.model_path(&args.path)?;
+    rules.dice_model().check(path)?;
+    let policy = PlayPolicy::new(args.joint_holds, args.bonus_aware, args.phased_weights, &rules);
 
     match args.command {
-        Commands::Learn {scores, rule, distr, game,full, bonus} => {
-            learn_models(&args.path, scores, rule, distr, game, full, bonus)?
+        Commands::Learn {scores, systematic, adaptive, resume, merge, exact, rule, distr, game,full, optimiser, paired, racing, lower_bound, bonus, optimal} => {
+            let optimiser = Optimiser::from_arg(&optimiser)?;
+            learn_models(path, scores, systematic, adaptive, resume, merge, exact, rule, distr, game, full, optimiser, paired, racing, lower_bound, bonus, optimal, &policy, &rules, args.seed)?
         },
         Commands::Export {scores, distr, weights} => {
-            export_models(&args.path, scores, distr, weights)?;
+            export_models(path, scores, distr, weights, &policy, &rules)?;
+        },
+        Commands::Play {interactive, simulate, bonus, optimal, dice_set} => {
+            play_game(path, &args.path, interactive, simulate, bonus, optimal, &dice_set, &policy, &rules, args.seed)?;
+        },
+        Commands::Validate {rule, worst} => {
+            validate_hands(path, rule, worst, &rules)?;
         },
-        Commands::Play {interactive} => {
-            play_game(&args.path, interactive)?;
+        Commands::AnalyseDice {dice_set, output} => {
+            analyse_dice(&args.path, &dice_set, output.as_deref())?;
         },
     }
 
@@ -101,51 +257,69 @@ fn main() -> Result<(), String> {
     Ok(())
 }
 
-fn learn_models(path: &str, scores: Option<i64>, rule: Option<usize>, distr: Option<i64>, game: Option<Vec<i64>>, full: bool, bonus: Option<u32>) -> Result<(), String> {
+#[allow(clippy::too_many_arguments)]
+fn learn_models(path: &str, scores: Option<i64>, systematic: bool, adaptive: Option<f64>, resume: bool, merge: Option<Vec<String>>, exact: bool, rule: Option<usize>, distr: Option<i64>, game: Option<Vec<i64>>, full: bool, optimiser: Optimiser, paired: bool, racing: bool, lower_bound: bool, bonus: Option<u32>, optimal: bool, policy: &PlayPolicy, rules: &Ruleset, seed: Option<u64>) -> Result<(), String> {
     if full {
         check_path_create_folder(path, Some(DEBUG_DIR))?;
     }
+    rules.dice_model().save(path)?;
 
     if let Some(laps) = scores {
         println!("Start learning rules");
-        learn_hands(laps, path, rule, full)?;
+        let sampling = if systematic {Sampling::Systematic} else {Sampling::Random};
+        learn_hands(laps, path, rule, full, sampling, resume, adaptive, rules, seed)?;
+    }
+
+    if let Some(from) = merge {
+        println!("Start merging rules");
+        merge_hands(path, &from, rule, rules)?;
+    }
+
+    if exact {
+        println!("Start exact learning rules");
+        learn_exact_hands(path, rule, rules)?;
     }
 
     if let Some(laps) = distr {
         println!("Start learning hand distributions");
-        learn_hand_distributions(laps, path, rule)?;
+        learn_hand_distributions(laps, path, rule, rules, seed)?;
     }
 
     if let Some(laps) = game {
         println!("Start learning game strategies");
-        strategy_learn(path, laps, bonus)?;
+        strategy_learn(path, laps, bonus, optimiser, paired, racing, lower_bound, policy, rules, seed)?;
+    }
+
+    if optimal {
+        println!("Start solving optimal game strategy");
+        learn_optimal_strategy(path, rules)?;
     }
 
     Ok(())
 }
 
-fn export_models(path: &str, scores: bool, distr: bool, weights: Option<u32>) -> Result<(), String> {
+fn export_models(path: &str, scores: bool, distr: bool, weights: Option<u32>, policy: &PlayPolicy, rules: &Ruleset) -> Result<(), String> {
     check_path_create_folder(path, Some(EXPORT_DIR))?;
 
     if scores {
         println!("Start loading rules");
-        let hand_rules = load_hands(path, false)?;
+        let hand_rules = load_hands(path, rules, false)?;
         println!("Start exporting rules");
         hand_rules.iter().for_each(|h| h.export_optimal_holds(path).unwrap());
     }
 
     if distr {
         println!("Start loading distributions");
-        let hand_distr = load_hand_distributions(path, false)?;
+        let hand_distr = load_hand_distributions(path, rules, false)?;
         println!("Start exporting rules");
         hand_distr.iter().for_each(|h| h.export_distribution(path).unwrap());
     }
 
     if let Some(bonus) = weights {
         println!("Start loading weights");
-        if let Some((generation, weights)) = load_weights(path, Some(bonus))? {
+        if let Some((generation, weights)) = load_weights(path, Some(policy.weights_suffix(bonus)), policy.n_weights(rules), rules.bonus_threshold(), bonus)? {
             println!("Start exporting weights");
-            export_weights(path, Some(bonus), generation, &weights)?;
+            export_weights(path, Some(policy.weights_suffix(bonus)), generation, &weights, policy, rules)?;
         } else {
             println!("No weights found in weights file");
         };
@@ -154,10 +328,20 @@ fn export_models(path: &str, scores: bool, distr: bool, weights: Option<u32>) ->
     Ok(())
 }
 
-fn play_game(path: &str, interactive: Option<u32>) -> Result<(), String> {
+#[allow(clippy::too_many_arguments)]
+fn play_game(path: &str, root_path: &str, interactive: Option<u32>, simulate: Option<u32>, bonus: Option<u32>, optimal: bool, dice_set: &str, policy: &PlayPolicy, rules: &Ruleset, seed: Option<u64>) -> Result<(), String> {
 
     if let Some(bonus) = interactive {
-        play_with_own_dices(path, bonus)?;
+        let mut roll_log = RollLog::open(root_path, dice_set)?;
+        play_with_own_dices(path, bonus, optimal, policy, rules, &mut roll_log, seed)?;
+    }
+
+    if let Some(laps) = simulate {
+        if optimal {
+            simulate_optimal(path, laps, rules, seed)?;
+        } else {
+            simulate_weights(path, laps, bonus.unwrap_or(rules.bonus()), policy, rules, seed)?;
+        }
     }
 
     Ok(())
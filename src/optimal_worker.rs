@@ -0,0 +1,358 @@
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::time::Instant;
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+use crate::dices::{Dices, Transitions};
+use crate::score_box::ruleset::Ruleset;
+use crate::score_box::scoring::ScoreBox;
+use crate::score_box::score_card::{ScoreCard, YAHTZEE_BONUS};
+use crate::utils::{base10_to_base2, base7_to_base10, records_in_file, thread_pool};
+
+/// Largest number of hands the state space is solved for, i.e. 2^15 hand combinations
+const MAX_HANDS: usize = 15;
+
+/// Expected values of one turn given a game state, one value per hold for every throw followed
+/// by a hold, and the value of the state before the first throw.
+struct TurnValues {
+    holds: Vec<Vec<f64>>,
+    value: f64,
+}
+
+/// Optimal game strategy given by the expected final score for every game state, i.e. the
+/// available hands (same bitmask as in play), the upper section sum capped at the bonus threshold
+/// and, for rulesets with Yahtzee bonuses, whether the Yahtzee hand is scored with 50.
+pub struct OptimalStrategy {
+    values: Vec<f32>,
+    rules: Ruleset,
+    all_hands: u32,
+    bonus_threshold: u16,
+    upper_states: usize,
+    yahtzee_states: usize,
+    boxes: Vec<ScoreBox>,
+    transitions: Transitions,
+    scores: Vec<Vec<u16>>,
+    yahtzees: Vec<bool>,
+}
+
+impl OptimalStrategy {
+    pub fn new(rules: &Ruleset) -> Result<OptimalStrategy, String> {
+        if rules.n_hands() > MAX_HANDS {
+            return Err(format!("Error, optimal strategy is only supported for rulesets with up to {} hands", MAX_HANDS));
+        }
+        if rules.saved_rolls() {
+            return Err(String::from("Error, optimal strategy is not supported with saved rolls"));
+        }
+
+        let boxes = rules.boxes().clone();
+        let transitions = Transitions::new(rules.n_dices(), rules.dice_model());
+
+        let scores = transitions
+            .throws
+            .iter()
+            .map(|t| boxes.iter().map(|b| b.score(t) as u16).collect::<Vec<u16>>())
+            .collect::<Vec<Vec<u16>>>();
+
+        let yahtzees = transitions
+            .throws
+            .iter()
+            .map(|t| t.iter().all(|&d| d == t[0]))
+            .collect::<Vec<bool>>();
+
+        let upper_states = rules.bonus_threshold() as usize + 1;
+        let yahtzee_states = if rules.yahtzee_hand().is_some() {2} else {1};
+        Ok(OptimalStrategy {
+            values: vec![0f32; (rules.all_hands() as usize + 1) * upper_states * yahtzee_states],
+            rules: rules.clone(),
+            all_hands: rules.all_hands(),
+            bonus_threshold: rules.bonus_threshold(),
+            upper_states,
+            yahtzee_states,
+            boxes,
+            transitions,
+            scores,
+            yahtzees,
+        })
+    }
+
+    /// Expected final score from a game state, excluding already scored hands
+    pub fn value(&self, available_hands: u32, upper_sum: u16, yahtzee_scored: bool) -> f32 {
+        self.values[self.state_index(available_hands, upper_sum, yahtzee_scored)]
+    }
+
+    fn state_index(&self, available_hands: u32, upper_sum: u16, yahtzee_scored: bool) -> usize {
+        let upper = upper_sum.min(self.bonus_threshold) as usize;
+        (available_hands as usize * self.upper_states + upper) * self.yahtzee_states + yahtzee_scored as usize
+    }
+
+    /// Whether thrown dices are a Yahtzee to be played by the joker rules
+    fn is_joker(&self, throw_idx: usize, available_hands: u32) -> bool {
+        self.yahtzees[throw_idx] && self.rules.is_joker(&self.transitions.throws[throw_idx], available_hands)
+    }
+
+    /// Value of scoring thrown dices in a hand, i.e. the score, any bonus reached and the value
+    /// of the resulting game state
+    fn hand_value(&self, throw_idx: usize, hand: usize, available_hands: u32, upper_sum: u16, yahtzee_scored: bool) -> f64 {
+        let joker = self.is_joker(throw_idx, available_hands);
+        let score = match self.boxes[hand].joker_score() {
+            Some(s) if joker => s as u16,
+            _ => self.scores[throw_idx][hand],
+        };
+
+        let mut next_upper = upper_sum;
+        let mut bonus = 0u32;
+        if hand < 6 {
+            next_upper = (upper_sum + score).min(self.bonus_threshold);
+            if upper_sum < self.bonus_threshold && next_upper >= self.bonus_threshold {
+                bonus = self.rules.bonus();
+            }
+        }
+        if joker && yahtzee_scored {
+            bonus += YAHTZEE_BONUS as u32;
+        }
+        let next_scored = yahtzee_scored || (self.rules.yahtzee_hand() == Some(hand as u8) && score > 0);
+
+        (score as u32 + bonus) as f64 + self.value(available_hands - (1 << hand), next_upper, next_scored) as f64
+    }
+
+    fn best_hand(&self, throw_idx: usize, hands: &[u8], available_hands: u32, upper_sum: u16, yahtzee_scored: bool) -> (usize, f64) {
+        let joker_hands: Vec<u8>;
+        let hands = if self.is_joker(throw_idx, available_hands) {
+            joker_hands = base10_to_base2(self.rules.allowed_hands(&self.transitions.throws[throw_idx], available_hands), false);
+            &joker_hands
+        } else {
+            hands
+        };
+
+        let mut best = (0usize, f64::MIN);
+        for &hand in hands {
+            let value = self.hand_value(throw_idx, hand as usize, available_hands, upper_sum, yahtzee_scored);
+            if value > best.1 {
+                best = (hand as usize, value);
+            }
+        }
+
+        best
+    }
+
+    fn turn_values(&self, available_hands: u32, upper_sum: u16, yahtzee_scored: bool) -> TurnValues {
+        let hands = base10_to_base2(self.rules.open_hands(available_hands), false);
+        let throw_values = (0..self.transitions.throws.len())
+            .map(|d| self.best_hand(d, &hands, available_hands, upper_sum, yahtzee_scored).1)
+            .collect::<Vec<f64>>();
+
+        let (holds, value) = self.transitions.turn_holds(throw_values, self.rules.rolls());
+
+        TurnValues { holds, value }
+    }
+
+    /// Plays one turn with optimal holds and returns the final dices
+    pub fn throw_hand(&self, dices: &mut Dices, score_card: &ScoreCard) -> Vec<u8> {
+        let tv = self.turn_values(
+            score_card.available_hands(),
+            score_card.upper_sum().min(self.bonus_threshold),
+            score_card.yahtzee_scored(&self.rules),
+        );
+
+        let mut thrown = dices.throw_and_hold(None);
+        for hold_values in &tv.holds {
+            let (h, _) = self.transitions.best_hold(self.transitions.throw_index[&base7_to_base10(&thrown)], hold_values);
+            thrown = dices.throw_and_hold(Some(self.transitions.hold(h)));
+        }
+
+        thrown
+    }
+
+    /// Returns the optimal hand to score thrown dices in together with the score
+    pub fn best_available_game_hand(&self, thrown: &[u8], score_card: &ScoreCard) -> (u8, u16) {
+        let available_hands = score_card.available_hands();
+        let throw_idx = self.transitions.throw_index[&base7_to_base10(thrown)];
+        let hands = base10_to_base2(self.rules.open_hands(available_hands), false);
+        let (hand, _) = self.best_hand(
+            throw_idx,
+            &hands,
+            available_hands,
+            score_card.upper_sum().min(self.bonus_threshold),
+            score_card.yahtzee_scored(&self.rules),
+        );
+
+        (hand as u8, self.rules.score(hand as u8, thrown, available_hands))
+    }
+
+    /// Forced order mode has a strategy of its own
+    fn file_name(&self) -> &str {
+        if self.rules.forced_order() {"optimal.forced.bin"} else {"optimal.bin"}
+    }
+
+    fn save(&self, path: &str) -> Result<(), String> {
+        let path_name = &format!("{}/{}", path, self.file_name());
+        let mut buf_writer = match File::create(path_name) {
+            Ok(f) => BufWriter::new(f),
+            Err(e) => return Err(format!("Error while open/create file {}: {}", path_name, e)),
+        };
+
+        let n_records = self.values.len() as u64;
+        if let Err(e) = buf_writer.write_all(&n_records.to_le_bytes()) {
+            return Err(format!("Error while writing to file {}: {}", path_name, e));
+        }
+
+        // The bonus rule the strategy is learned for
+        let mut buf = [0u8; 6];
+        buf[0..2].copy_from_slice(&self.bonus_threshold.to_le_bytes());
+        buf[2..6].copy_from_slice(&self.rules.bonus().to_le_bytes());
+        if let Err(e) = buf_writer.write_all(&buf) {
+            return Err(format!("Error while writing to file {}: {}", path_name, e));
+        }
+
+        for value in &self.values {
+            if let Err(e) = buf_writer.write_all(&value.to_le_bytes()) {
+                return Err(format!("Error while writing to file {}: {}", path_name, e));
+            }
+        }
+        if let Err(e) = buf_writer.flush() {
+            return Err(format!("Error while writing to file {}: {}", path_name, e));
+        }
+        Ok(())
+    }
+
+    fn load(&mut self, path: &str) -> Result<(), String> {
+        let path_name = &format!("{}/{}", path, self.file_name());
+        let mut buf_reader = match File::open(path_name) {
+            Ok(f) => BufReader::new(f),
+            Err(e) => return Err(format!("Error while open file {}: {}", path_name, e)),
+        };
+
+        let n_records = records_in_file(&mut buf_reader, path_name)?;
+        if n_records as usize != self.values.len() {
+            return Err(format!("Error, unexpected number of records in file {}", path_name));
+        }
+
+        let mut buf = [0u8; 6];
+        if let Err(e) = buf_reader.read_exact(&mut buf) {
+            return Err(format!("Error while reading from file {}: {}", path_name, e));
+        }
+        let bonus_threshold = u16::from_le_bytes(buf[0..2].try_into().unwrap());
+        let bonus = u32::from_le_bytes(buf[2..6].try_into().unwrap());
+        if bonus_threshold != self.bonus_threshold || bonus != self.rules.bonus() {
+            return Err(format!("Error, file {} is learned for a bonus of {} at {}, not {} at {}",
+                               path_name, bonus, bonus_threshold, self.rules.bonus(), self.bonus_threshold));
+        }
+
+        let mut buf = [0u8; 4];
+        for value in self.values.iter_mut() {
+            match buf_reader.read_exact(&mut buf) {
+                Ok(()) => *value = f32::from_le_bytes(buf),
+                Err(e) => return Err(format!("Error while reading from file {}: {}", path_name, e)),
+            }
+        }
+        Ok(())
+    }
+}
+
+pub fn learn_optimal_strategy(path: &str, rules: &Ruleset) -> Result<(), String> {
+    let pool = thread_pool()?;
+    let mut strategy = OptimalStrategy::new(rules)?;
+    let reachable = reachable_upper_sums(rules.n_dices(), rules.bonus_threshold());
+    let yahtzee_hand = rules.yahtzee_hand();
+
+    // Work backwards from the last hand, every state only depends on states with fewer hands left
+    for n_left in 1..=rules.n_hands() as u32 {
+        let begin = Instant::now();
+        // In forced order mode only the bottom hands can be left
+        let masks = (1..=strategy.all_hands)
+            .filter(|m| m.count_ones() == n_left)
+            .filter(|&m| !rules.forced_order() || m | ((m & m.wrapping_neg()) - 1) == strategy.all_hands)
+            .collect::<Vec<u32>>();
+
+        let values = pool.install(|| masks
+            .into_par_iter()
+            .map(|m| {
+                let scored_upper = (!m & 0b111111) as usize;
+                // The Yahtzee hand can only have been scored with 50 once it is filled
+                let yahtzee_scored = match yahtzee_hand {
+                    Some(y) if m & (1 << y) == 0 => vec![false, true],
+                    _ => vec![false],
+                };
+                let upper_values = (0..strategy.upper_states as u16)
+                    .filter(|&u| reachable[scored_upper][u as usize])
+                    .flat_map(|u| yahtzee_scored.iter().map(move |&y| (u, y)))
+                    .map(|(u, y)| (strategy.state_index(m, u, y), strategy.turn_values(m, u, y).value as f32))
+                    .collect::<Vec<(usize, f32)>>();
+                upper_values
+            })
+            .collect::<Vec<Vec<(usize, f32)>>>());
+
+        for upper_values in values {
+            for (i, v) in upper_values {
+                strategy.values[i] = v;
+            }
+        }
+        println!("...states with {:2} hands left done in {:.1}s", n_left, begin.elapsed().as_secs_f32());
+    }
+
+    println!("Expected score with optimal play: {:6.2}", strategy.value(strategy.all_hands, 0, false));
+    strategy.save(path)
+}
+
+pub fn load_optimal_strategy(path: &str, rules: &Ruleset) -> Result<OptimalStrategy, String> {
+    let mut strategy = OptimalStrategy::new(rules)?;
+    strategy.load(path)?;
+
+    Ok(strategy)
+}
+
+pub fn simulate_optimal(path: &str, laps: u32, rules: &Ruleset, seed: Option<u64>) -> Result<(), String> {
+    let strategy = load_optimal_strategy(path, rules)?;
+    let mut dices = Dices::from_seed(rules.n_dices(), rules.dice_model(), seed, 0);
+
+    let (total_score, total_bonus) = run(&strategy, laps, &mut dices);
+    println!("Optimal strategy average score over {} games: {:6.2} (expected {:6.2})",
+             laps, (total_score + total_bonus) as f64 / laps as f64, strategy.value(strategy.all_hands, 0, false));
+
+    Ok(())
+}
+
+fn run(strategy: &OptimalStrategy, laps: u32, dices: &mut Dices) -> (u64, u64) {
+    let mut total_score = 0u64;
+    let mut total_bonus = 0u64;
+
+    for _ in 0..laps {
+        let mut score_card = ScoreCard::new(&strategy.rules);
+
+        while score_card.available_hands() > 0 {
+            let thrown = strategy.throw_hand(dices, &score_card);
+            let (hand, _) = strategy.best_available_game_hand(&thrown, &score_card);
+            score_card.score(&strategy.rules, hand, &thrown);
+        }
+
+        total_score += score_card.total() as u64;
+        total_bonus += score_card.bonus(&strategy.rules, strategy.rules.bonus()) as u64;
+    }
+
+    (total_score, total_bonus)
+}
+
+/// For every combination of scored upper hands, which capped upper sums that can be reached
+fn reachable_upper_sums(n_dies: usize, bonus_threshold: u16) -> Vec<Vec<bool>> {
+    let upper_states = bonus_threshold as usize + 1;
+    let mut res = vec![vec![false; upper_states]; 64];
+
+    for (scored, reachable) in res.iter_mut().enumerate() {
+        let mut sums = vec![false; upper_states];
+        sums[0] = true;
+        for face in 1..=6u16 {
+            if scored & (1 << (face - 1)) == 0 {
+                continue;
+            }
+            let mut next = vec![false; upper_states];
+            for (s, _) in sums.iter().enumerate().filter(|(_, &r)| r) {
+                for n in 0..=n_dies as u16 {
+                    next[(s as u16 + n * face).min(bonus_threshold) as usize] = true;
+                }
+            }
+            sums = next;
+        }
+        *reachable = sums;
+    }
+
+    res
+}
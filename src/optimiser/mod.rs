@@ -0,0 +1,277 @@
+use rand::rngs::StdRng;
+use rand::Rng;
+
+/// Spread of the first CMA-ES generation around the starting weights, as the grid step
+const INITIAL_SIGMA: f64 = 0.1;
+
+/// Children bred per genetic generation
+const GA_POPULATION: usize = 64;
+
+/// Spread of a mutated weight in the genetic algorithm
+const GA_MUTATION: f64 = 0.1;
+
+/// Optimiser searching the weights in game strategy learning
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Optimiser {
+    /// Every combination of -0.1, 0 and +0.1 around the best weights
+    Grid,
+    /// Covariance matrix adaptation evolution strategy
+    CmaEs,
+    /// Genetic algorithm breeding from the best weights found so far
+    Genetic,
+}
+
+impl Optimiser {
+    pub fn from_arg(arg: &str) -> Result<Optimiser, String> {
+        match arg {
+            "grid" => Ok(Optimiser::Grid),
+            "cma-es" => Ok(Optimiser::CmaEs),
+            "genetic" => Ok(Optimiser::Genetic),
+            _ => Err(format!("Error, unknown optimiser {}, use grid, cma-es or genetic", arg)),
+        }
+    }
+}
+
+/// Search state of the optimisers evaluating a population of candidate weights per generation
+pub enum Search {
+    CmaEs(Box<CmaEs>),
+    Genetic,
+}
+
+impl Search {
+    /// Search starting from the best weights so far, none for the grid which runs on its own
+    pub fn new(optimiser: Optimiser, start: &[f32]) -> Option<Search> {
+        match optimiser {
+            Optimiser::Grid => None,
+            Optimiser::CmaEs => Some(Search::CmaEs(Box::new(CmaEs::new(start)))),
+            Optimiser::Genetic => Some(Search::Genetic),
+        }
+    }
+
+    /// Candidate weights of the next generation, pool holds the weights found so far best first
+    pub fn ask(&mut self, pool: &[&[f32]], rng: &mut StdRng) -> Vec<Vec<f32>> {
+        match self {
+            Search::CmaEs(cma_es) => cma_es.ask(rng),
+            Search::Genetic => breed(pool, rng),
+        }
+    }
+
+    /// Learns from the average scores of the candidates of a generation
+    pub fn tell(&mut self, candidates: &[Vec<f32>], scores: &[f32]) {
+        match self {
+            Search::CmaEs(cma_es) => cma_es.tell(candidates, scores),
+            Search::Genetic => (),
+        }
+    }
+}
+
+/// Children by tournament selection, uniform crossover and gaussian mutation
+fn breed(pool: &[&[f32]], rng: &mut StdRng) -> Vec<Vec<f32>> {
+    // The pool is ranked, of two random picks the lower index is the fitter
+    let select = |rng: &mut StdRng| pool[rng.gen_range(0..pool.len()).min(rng.gen_range(0..pool.len()))];
+
+    (0..GA_POPULATION)
+        .map(|_| {
+            let (mother, father) = (select(rng), select(rng));
+            let n = mother.len();
+            (0..n)
+                .map(|i| {
+                    let gene = if rng.gen_bool(0.5) {mother[i]} else {father[i]};
+                    if rng.gen_bool(1.0 / n as f64) {
+                        gene + (GA_MUTATION * gaussian(rng)) as f32
+                    } else {
+                        gene
+                    }
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// CMA-ES maximising the average score, following Hansen's tutorial
+pub struct CmaEs {
+    n: usize,
+    lambda: usize,
+    recombination: Vec<f64>,
+    mu_eff: f64,
+    cc: f64,
+    cs: f64,
+    c1: f64,
+    cmu: f64,
+    damps: f64,
+    chi_n: f64,
+    mean: Vec<f64>,
+    sigma: f64,
+    pc: Vec<f64>,
+    ps: Vec<f64>,
+    cov: Vec<Vec<f64>>,
+    eigen_vectors: Vec<Vec<f64>>,
+    eigen_sqrt: Vec<f64>,
+    generation: u32,
+}
+
+impl CmaEs {
+    fn new(start: &[f32]) -> CmaEs {
+        let n = start.len();
+        let nf = n as f64;
+        let lambda = 4 + (3.0 * nf.ln()).floor() as usize;
+        let mu = lambda / 2;
+
+        let mut recombination = (0..mu)
+            .map(|i| (mu as f64 + 0.5).ln() - (i as f64 + 1.0).ln())
+            .collect::<Vec<f64>>();
+        let sum: f64 = recombination.iter().sum();
+        recombination.iter_mut().for_each(|w| *w /= sum);
+        let mu_eff = 1.0 / recombination.iter().map(|w| w * w).sum::<f64>();
+
+        let cc = (4.0 + mu_eff / nf) / (nf + 4.0 + 2.0 * mu_eff / nf);
+        let cs = (mu_eff + 2.0) / (nf + mu_eff + 5.0);
+        let c1 = 2.0 / ((nf + 1.3).powi(2) + mu_eff);
+        let cmu = (1.0 - c1).min(2.0 * (mu_eff - 2.0 + 1.0 / mu_eff) / ((nf + 2.0).powi(2) + mu_eff));
+        let damps = 1.0 + 2.0 * (((mu_eff - 1.0) / (nf + 1.0)).sqrt() - 1.0).max(0.0) + cs;
+        let chi_n = nf.sqrt() * (1.0 - 1.0 / (4.0 * nf) + 1.0 / (21.0 * nf * nf));
+
+        CmaEs {
+            n,
+            lambda,
+            recombination,
+            mu_eff,
+            cc,
+            cs,
+            c1,
+            cmu,
+            damps,
+            chi_n,
+            mean: start.iter().map(|&w| w as f64).collect(),
+            sigma: INITIAL_SIGMA,
+            pc: vec![0.0; n],
+            ps: vec![0.0; n],
+            cov: identity(n),
+            eigen_vectors: identity(n),
+            eigen_sqrt: vec![1.0; n],
+            generation: 0,
+        }
+    }
+
+    /// Samples mean + sigma * B * D * z with z standard normal
+    fn ask(&mut self, rng: &mut StdRng) -> Vec<Vec<f32>> {
+        (0..self.lambda)
+            .map(|_| {
+                let dz = self.eigen_sqrt.iter().map(|d| d * gaussian(rng)).collect::<Vec<f64>>();
+                (0..self.n)
+                    .map(|i| (self.mean[i] + self.sigma * dot(&self.eigen_vectors[i], &dz)) as f32)
+                    .collect()
+            })
+            .collect()
+    }
+
+    fn tell(&mut self, candidates: &[Vec<f32>], scores: &[f32]) {
+        let n = self.n;
+        self.generation += 1;
+
+        let mut ranked = (0..candidates.len()).collect::<Vec<usize>>();
+        ranked.sort_by(|&a, &b| scores[b].total_cmp(&scores[a]));
+        let steps = ranked
+            .iter()
+            .take(self.recombination.len())
+            .map(|&c| (0..n).map(|i| (candidates[c][i] as f64 - self.mean[i]) / self.sigma).collect())
+            .collect::<Vec<Vec<f64>>>();
+
+        // Weighted step of the mean, in units of sigma
+        let step = (0..n)
+            .map(|i| self.recombination.iter().zip(&steps).map(|(w, s)| w * s[i]).sum())
+            .collect::<Vec<f64>>();
+        (0..n).for_each(|i| self.mean[i] += self.sigma * step[i]);
+
+        // Evolution paths, the one for sigma through the inverse square root of the covariance
+        let b_step = (0..n).map(|j| (0..n).map(|i| self.eigen_vectors[i][j] * step[i]).sum::<f64>() / self.eigen_sqrt[j]).collect::<Vec<f64>>();
+        let inv_sqrt_step = (0..n).map(|i| dot(&self.eigen_vectors[i], &b_step)).collect::<Vec<f64>>();
+        let cs_norm = (self.cs * (2.0 - self.cs) * self.mu_eff).sqrt();
+        (0..n).for_each(|i| self.ps[i] = (1.0 - self.cs) * self.ps[i] + cs_norm * inv_sqrt_step[i]);
+
+        let ps_norm = dot(&self.ps, &self.ps).sqrt();
+        let stalled = ps_norm / (1.0 - (1.0 - self.cs).powi(2 * self.generation as i32)).sqrt() / self.chi_n
+            >= 1.4 + 2.0 / (n as f64 + 1.0);
+        let h_sig = if stalled {0.0} else {1.0};
+        let cc_norm = (self.cc * (2.0 - self.cc) * self.mu_eff).sqrt();
+        (0..n).for_each(|i| self.pc[i] = (1.0 - self.cc) * self.pc[i] + h_sig * cc_norm * step[i]);
+
+        // Rank-one and rank-mu update of the covariance
+        let decay = 1.0 - self.c1 - self.cmu + (1.0 - h_sig) * self.c1 * self.cc * (2.0 - self.cc);
+        for i in 0..n {
+            for j in 0..=i {
+                let rank_mu: f64 = self.recombination.iter().zip(&steps).map(|(w, s)| w * s[i] * s[j]).sum();
+                let c = decay * self.cov[i][j] + self.c1 * self.pc[i] * self.pc[j] + self.cmu * rank_mu;
+                self.cov[i][j] = c;
+                self.cov[j][i] = c;
+            }
+        }
+
+        self.sigma *= ((self.cs / self.damps) * (ps_norm / self.chi_n - 1.0)).exp();
+
+        let (values, vectors) = jacobi_eigen(&self.cov);
+        self.eigen_sqrt = values.iter().map(|v| v.max(1e-20).sqrt()).collect();
+        self.eigen_vectors = vectors;
+    }
+}
+
+/// Eigenvalues and eigenvectors, as columns, of a symmetric matrix by cyclic Jacobi rotations
+fn jacobi_eigen(matrix: &[Vec<f64>]) -> (Vec<f64>, Vec<Vec<f64>>) {
+    let n = matrix.len();
+    let mut a = matrix.to_vec();
+    let mut v = identity(n);
+
+    for _ in 0..100 {
+        let off: f64 = (0..n).map(|i| (i + 1..n).map(|j| a[i][j] * a[i][j]).sum::<f64>()).sum();
+        if off < 1e-22 {
+            break;
+        }
+
+        for p in 0..n {
+            for q in p + 1..n {
+                if a[p][q].abs() < 1e-300 {
+                    continue;
+                }
+
+                let theta = (a[q][q] - a[p][p]) / (2.0 * a[p][q]);
+                let t = theta.signum() / (theta.abs() + (theta * theta + 1.0).sqrt());
+                let c = 1.0 / (t * t + 1.0).sqrt();
+                let s = t * c;
+
+                for row in a.iter_mut() {
+                    let (akp, akq) = (row[p], row[q]);
+                    row[p] = c * akp - s * akq;
+                    row[q] = s * akp + c * akq;
+                }
+                let (upper, lower) = a.split_at_mut(q);
+                for (apk, aqk) in upper[p].iter_mut().zip(lower[0].iter_mut()) {
+                    let (x, y) = (*apk, *aqk);
+                    *apk = c * x - s * y;
+                    *aqk = s * x + c * y;
+                }
+                for row in v.iter_mut() {
+                    let (vkp, vkq) = (row[p], row[q]);
+                    row[p] = c * vkp - s * vkq;
+                    row[q] = s * vkp + c * vkq;
+                }
+            }
+        }
+    }
+
+    ((0..n).map(|i| a[i][i]).collect(), v)
+}
+
+fn identity(n: usize) -> Vec<Vec<f64>> {
+    (0..n).map(|i| (0..n).map(|j| if i == j {1.0} else {0.0}).collect()).collect()
+}
+
+fn dot(a: &[f64], b: &[f64]) -> f64 {
+    a.iter().zip(b).map(|(x, y)| x * y).sum()
+}
+
+/// Standard normal sample by the Box-Muller transform
+fn gaussian(rng: &mut StdRng) -> f64 {
+    let u: f64 = 1.0 - rng.gen::<f64>();
+    let v: f64 = rng.gen();
+    (-2.0 * u.ln()).sqrt() * (2.0 * std::f64::consts::PI * v).cos()
+}
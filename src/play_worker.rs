@@ -2,29 +2,248 @@ use std::collections::HashMap;
 use std::io::stdin;
 use std::str::FromStr;
 use colored::{ColoredString, Colorize};
-use crate::dices::{Dices, Throw};
+use crate::dice_worker::RollLog;
+use crate::dices::{Dices, Transitions};
 use crate::weight_worker::{best_available_game_hand, load_weights};
 use crate::hand_worker::load_hands;
 use crate::score_box::rules::{best_available_hand, Hand};
+use crate::score_box::ruleset::Ruleset;
+use crate::score_box::score_card::ScoreCard;
+use crate::optimal_worker::{load_optimal_strategy, OptimalStrategy};
 use crate::utils::{base10_to_base2, base10_to_base7, base7_to_base10, initcap};
 
-pub fn play_with_own_dices(path: &str, bonus: u32) -> Result<(), String> {
-    let mut human_available_hands: u16 = 32767;
-    let mut mc_available_hands: u16 = 32767;
-    let mut human_scores: HashMap<u8, u16> = HashMap::new();
-    let mut mc_scores: HashMap<u8, u16> = HashMap::new();
-
-    let mut dices = Dices::new();
-    let hands = load_hands(path, true)?;
-    let weights_vec = load_weights(path, Some(bonus))?;
-    let weights = if let Some((_, w)) = weights_vec {
-        if w.len() > 0 {
-            w[0].weights
+/// How the MC player picks holds in a turn played by weights
+enum HoldPolicy {
+    /// Optimal holds of the single most promising hand
+    TargetHand,
+    /// Holds giving the best expected weighted score over all open hands, valued as when
+    /// choosing the hand to score in
+    Joint(Box<Transitions>),
+}
+
+/// Stages of a game by the number of hands scored, early, middle and late
+const STAGES: usize = 3;
+
+/// How the MC player plays a turn by weights, the holds, whether the hand to score in also
+/// accounts for the odds of reaching the upper section bonus and whether the weights depend on
+/// the phase of the game
+pub struct PlayPolicy {
+    holds: HoldPolicy,
+    bonus_odds: Option<BonusOdds>,
+    phased: bool,
+}
+
+impl PlayPolicy {
+    /// Bonus awareness implies joint holds, the holds are picked by the same values as the hand
+    pub fn new(joint: bool, bonus_aware: bool, phased: bool, rules: &Ruleset) -> PlayPolicy {
+        let holds = if joint || bonus_aware {
+            HoldPolicy::Joint(Box::new(Transitions::new(rules.n_dices(), rules.dice_model())))
         } else {
-            return Err(String::from("Error, no weights in weights file"));
+            HoldPolicy::TargetHand
+        };
+        let bonus_odds = bonus_aware.then(|| BonusOdds::new(rules));
+
+        PlayPolicy { holds, bonus_odds, phased }
+    }
+
+    /// Weights are learned per bonus and policy
+    pub fn weights_suffix(&self, bonus: u32) -> String {
+        let suffix = match (&self.holds, &self.bonus_odds) {
+            (_, Some(_)) => format!("{}.upper", bonus),
+            (HoldPolicy::Joint(_), None) => format!("{}.joint", bonus),
+            (HoldPolicy::TargetHand, None) => bonus.to_string(),
+        };
+        if self.phased {format!("{}.phased", suffix)} else {suffix}
+    }
+
+    /// Number of weight sets, one per phase of the game, or a single set used throughout
+    pub fn n_phases(&self) -> usize {
+        if self.phased {2 * STAGES} else {1}
+    }
+
+    /// Number of weights, one per hand in every phase
+    pub fn n_weights(&self, rules: &Ruleset) -> usize {
+        self.n_phases() * rules.n_hands()
+    }
+
+    /// Phase of the game by the stage and whether the upper section bonus is still to be won
+    pub fn phase(&self, score_card: &ScoreCard, rules: &Ruleset) -> usize {
+        if !self.phased {
+            return 0;
+        }
+
+        let scored = rules.n_hands() - score_card.available_hands().count_ones() as usize;
+        let stage = scored * STAGES / rules.n_hands();
+        2 * stage + if score_card.bonus_open(rules) {0} else {1}
+    }
+
+    pub fn phase_name(&self, phase: usize) -> String {
+        if !self.phased {
+            return String::from("all");
+        }
+
+        let stage = ["early", "middle", "late"][phase / 2];
+        format!("{}, bonus {}", stage, if phase.is_multiple_of(2) {"open"} else {"settled"})
+    }
+
+    /// Weights of the hands in the current phase of the game
+    pub fn phase_weights<'a>(&self, weights: &'a [f32], score_card: &ScoreCard, rules: &Ruleset) -> &'a [f32] {
+        let n_hands = rules.n_hands();
+        let phase = self.phase(score_card, rules);
+        &weights[phase * n_hands..(phase + 1) * n_hands]
+    }
+
+    /// Plays a turn and returns the final dices together with the number of rolls used
+    pub fn throw_hand(&self, dices: &mut Dices, score_card: &ScoreCard, hands: &[Hand], weights: &[f32], bonus: u32, rules: &Ruleset) -> Result<(Vec<u8>, usize), String> {
+        let rolls = score_card.turn_rolls(rules);
+
+        match &self.holds {
+            HoldPolicy::TargetHand => {
+                let open_hands = rules.open_hands(score_card.available_hands());
+                throw_hand(dices, open_hands, rolls, rules.saved_rolls(), hands)
+            }
+            HoldPolicy::Joint(transitions) => {
+                Ok(self.throw_joint_hand(transitions, dices, score_card, rolls, weights, bonus, rules))
+            }
+        }
+    }
+
+    /// Value of scoring in a hand, the weighted score and with bonus odds also the change in
+    /// expected upper section bonus
+    pub fn hand_value(&self, hand: u8, score: u16, weights: &[f32], score_card: &ScoreCard, bonus: u32) -> f32 {
+        let weighted_score = score as f32 * weights[hand as usize];
+        match &self.bonus_odds {
+            Some(bonus_odds) => weighted_score + (bonus as f64 * bonus_odds.gain(score_card, hand, score)) as f32,
+            None => weighted_score,
+        }
+    }
+
+    /// Plays a turn by the holds giving the best expected hand value over all open hands and
+    /// returns the final dices together with the number of rolls used. When rolls can be saved the
+    /// turn stops once holding all dices is best.
+    #[allow(clippy::too_many_arguments)]
+    fn throw_joint_hand(&self, transitions: &Transitions, dices: &mut Dices, score_card: &ScoreCard, rolls: usize, weights: &[f32], bonus: u32, rules: &Ruleset) -> (Vec<u8>, usize) {
+        let available_hands = score_card.available_hands();
+        let weights = self.phase_weights(weights, score_card, rules);
+        let throw_values = transitions
+            .throws
+            .iter()
+            .map(|t| {
+                base10_to_base2(rules.allowed_hands(t, available_hands), false)
+                    .into_iter()
+                    .map(|h| self.hand_value(h, rules.score(h, t, available_hands), weights, score_card, bonus) as f64)
+                    .fold(f64::MIN, f64::max)
+            })
+            .collect::<Vec<f64>>();
+        let (holds, _) = transitions.turn_holds(throw_values, rolls);
+
+        let mut thrown = dices.throw_and_hold(None);
+        for (throw, hold_values) in holds.iter().enumerate() {
+            let t_code = base7_to_base10(&thrown);
+            let (h, _) = transitions.best_hold(transitions.throw_index[&t_code], hold_values);
+
+            if rules.saved_rolls() && transitions.hold_codes[h] == t_code {
+                return (thrown, throw + 1);
+            }
+            thrown = dices.throw_and_hold(Some(transitions.hold(h)));
+        }
+
+        (thrown, rolls)
+    }
+}
+
+/// Probability of reaching the upper section bonus by the upper hands still open and the upper
+/// sum so far. Every open upper hand is taken as played alone keeping all dices of its face, which
+/// makes the count of the face binomial.
+struct BonusOdds {
+    threshold: u16,
+    odds: Vec<f64>,
+}
+
+impl BonusOdds {
+    fn new(rules: &Ruleset) -> BonusOdds {
+        let threshold = rules.bonus_threshold();
+        let width = threshold as usize + 1;
+
+        // Indexed by the open upper hands bitmask and the upper sum capped at the threshold
+        let mut odds = vec![0f64; 64 * width];
+        odds[threshold as usize] = 1.0;
+        for open in 1..64usize {
+            let face = open.trailing_zeros() as usize;
+            let rest = open & (open - 1);
+            let count_odds = face_count_odds(rules, face as u8 + 1);
+            for sum in 0..width {
+                odds[open * width + sum] = count_odds
+                    .iter()
+                    .enumerate()
+                    .map(|(k, p)| p * odds[rest * width + (sum + k * (face + 1)).min(threshold as usize)])
+                    .sum();
+            }
+        }
+
+        BonusOdds { threshold, odds }
+    }
+
+    fn probability(&self, open_upper: u32, upper_sum: u16) -> f64 {
+        self.odds[open_upper as usize * (self.threshold as usize + 1) + upper_sum.min(self.threshold) as usize]
+    }
+
+    /// Change in the probability of reaching the bonus from scoring in a hand, zero for the lower
+    /// section
+    fn gain(&self, score_card: &ScoreCard, hand: u8, score: u16) -> f64 {
+        if hand >= 6 {
+            return 0.0;
         }
+
+        let open_upper = score_card.available_hands() & 0b111111;
+        let upper_sum = score_card.upper_sum();
+        self.probability(open_upper - (1 << hand), upper_sum + score) - self.probability(open_upper, upper_sum)
+    }
+}
+
+/// Probabilities of ending a hand with 0 to n dices of a face when keeping all dices of the face
+fn face_count_odds(rules: &Ruleset, face: u8) -> Vec<f64> {
+    let n_dices = rules.n_dices();
+    let hit = 1.0 - (1.0 - rules.dice_model().probability(face)).powi(rules.rolls() as i32);
+
+    let mut odds = vec![0f64; n_dices + 1];
+    let mut binomial = 1.0;
+    for (k, p) in odds.iter_mut().enumerate() {
+        *p = binomial * hit.powi(k as i32) * (1.0 - hit).powi((n_dices - k) as i32);
+        binomial = binomial * (n_dices - k) as f64 / (k + 1) as f64;
+    }
+
+    odds
+}
+
+/// Strategy used by the MC player
+enum McStrategy {
+    Weights(Vec<f32>),
+    Optimal(Box<OptimalStrategy>),
+}
+
+pub fn play_with_own_dices(path: &str, bonus: u32, optimal: bool, policy: &PlayPolicy, rules: &Ruleset, roll_log: &mut RollLog, seed: Option<u64>) -> Result<(), String> {
+    let mut human_card = ScoreCard::new(rules);
+    let mut mc_card = ScoreCard::new(rules);
+
+    let mut dices = Dices::from_seed(rules.n_dices(), rules.dice_model(), seed, 0);
+    let hands = load_hands(path, rules, true)?;
+    let strategy = if optimal {
+        McStrategy::Optimal(Box::new(load_optimal_strategy(path, rules)?))
+    } else if rules.forced_order() {
+        // Weights make no difference when there is only one hand to choose from
+        McStrategy::Weights(vec![1f32; policy.n_weights(rules)])
     } else {
-        return Err(String::from("Error, no weights file available"));
+        let weights_vec = load_weights(path, Some(policy.weights_suffix(bonus)), policy.n_weights(rules), rules.bonus_threshold(), bonus)?;
+        if let Some((_, mut w)) = weights_vec {
+            if !w.is_empty() {
+                McStrategy::Weights(w.swap_remove(0).weights)
+            } else {
+                return Err(String::from("Error, no weights in weights file"));
+            }
+        } else {
+            return Err(String::from("Error, no weights file available"));
+        }
     };
 
     let hand_names = hands
@@ -36,42 +255,45 @@ pub fn play_with_own_dices(path: &str, bonus: u32) -> Result<(), String> {
         })
         .collect::<Vec<String>>();
 
-    print_score_card(&human_scores, &mc_scores, &hand_names, (0, 0));
-    println!("Input dices without separators, e.g. 13234");
-    while human_available_hands > 0 {
-        let (thrown, hand) = query_human_input(human_available_hands);
-        let score = hands[hand as usize].score(&thrown);
-        human_scores.insert(hand, score as u16);
-        human_available_hands -= 2u16.pow(hand as u32);
+    print_score_card(&human_card, &mc_card, &hand_names, (0, 0), rules);
+    println!("Input dices without separators, e.g. {}", &"132346"[..rules.n_dices()]);
+    while human_card.available_hands() > 0 {
+        let (thrown, hand, rolls_used) = query_human_input(&human_card, rules, roll_log)?;
+        human_card.score(rules, hand, &thrown);
+        human_card.save_rolls(rules, rolls_used);
 
-        let (dices, mc_hand) = query_mc_input(&mut dices, mc_available_hands, &hands, weights)?;
-        let score = hands[mc_hand as usize].score(&dices);
-        mc_scores.insert(mc_hand, score as u16);
-        mc_available_hands -= 2u16.pow(mc_hand as u32);
+        let (dices, mc_hand, rolls_used) = query_mc_input(&mut dices, &mc_card, &hands, &strategy, bonus, policy, rules)?;
+        mc_card.score(rules, mc_hand, &dices);
+        mc_card.save_rolls(rules, rolls_used);
 
-        print_score_card(&human_scores, &mc_scores, &hand_names, (hand, mc_hand));
+        print_score_card(&human_card, &mc_card, &hand_names, (hand, mc_hand), rules);
     }
 
     Ok(())
 }
 
-fn query_human_input(available_hands: u16) -> (Vec<u8>, u8) {
-    let throw = [
-        ("First throw:", "First hold:"),
-        ("Second throw:", "Second hold:"),
-        ("Third throw:", "")
-    ];
+fn query_human_input(score_card: &ScoreCard, rules: &Ruleset, roll_log: &mut RollLog) -> Result<(Vec<u8>, u8, usize), String> {
+    let n_dices = rules.n_dices();
+    let ordinals = ["First", "Second", "Third", "Fourth", "Fifth", "Sixth"];
+    let rolls = score_card.turn_rolls(rules);
+    if rules.saved_rolls() {
+        println!("You have {} rolls, stay with your dices to save the rest", rolls);
+    }
 
     let mut t_vec: Vec<u8> = Vec::new();
     let mut h_vec: Vec<u8> = Vec::new();
-    for (i, t) in throw.iter().enumerate() {
-        t_vec = get_dices_input(t.0, None, Some(&h_vec));
+    let mut rolls_used = rolls;
+    for i in 0..rolls {
+        let ordinal = ordinals.get(i).map_or_else(|| format!("{}th", i + 1), |o| o.to_string());
+        t_vec = get_dices_input(&format!("{} throw:", ordinal), None, Some(&h_vec), n_dices);
+        roll_log.log(&thrown_dices(&t_vec, &h_vec))?;
         println!("Your dices: {:?}", t_vec);
 
-        if i < 2 {
-            h_vec = get_dices_input(t.1, Some(&t_vec), None);
-            if h_vec.len() == 5 {
+        if i < rolls - 1 {
+            h_vec = get_dices_input(&format!("{} hold:", ordinal), Some(&t_vec), None, n_dices);
+            if h_vec.len() == n_dices {
                 println!("\nYou stayed with dices: {:?}", h_vec);
+                rolls_used = i + 1;
                 break;
             } else {
                 println!("\nYou are holding: {:?}", h_vec);
@@ -80,33 +302,65 @@ fn query_human_input(available_hands: u16) -> (Vec<u8>, u8) {
     }
     println!();
 
-    let hand = get_hand_choice(base10_to_base2(available_hands, true));
-    (t_vec, hand)
+    if rules.is_joker(&t_vec, score_card.available_hands()) {
+        println!("Yahtzee joker!");
+    }
+    let hand = get_hand_choice(base10_to_base2(score_card.allowed_hands(rules, &t_vec), true));
+    Ok((t_vec, hand, rolls_used))
 }
 
-fn query_mc_input(dices: &mut Dices, available_hands: u16, hands: &Vec<Box<Hand>>, weights: [f32;15]) -> Result<(Vec<u8>, u8), String> {
+/// Dices physically thrown, i.e. the dices not held
+fn thrown_dices(dices: &[u8], hold: &[u8]) -> Vec<u8> {
+    let mut thrown = dices.to_vec();
+    for h in hold {
+        if let Some(pos) = thrown.iter().position(|d| d == h) {
+            thrown.remove(pos);
+        }
+    }
 
-    let t3 = throw_hand(dices, available_hands, hands)?;
-    let (h3, _) = best_available_game_hand(&t3, available_hands, hands, weights)?;
+    thrown
+}
+
+fn query_mc_input(dices: &mut Dices, score_card: &ScoreCard, hands: &[Hand], strategy: &McStrategy, bonus: u32, policy: &PlayPolicy, rules: &Ruleset) -> Result<(Vec<u8>, u8, usize), String> {
+
+    match strategy {
+        McStrategy::Weights(weights) => {
+            let (t3, rolls_used) = policy.throw_hand(dices, score_card, hands, weights, bonus, rules)?;
+            let (h3, _) = best_available_game_hand(&t3, score_card, rules, weights, bonus, policy)?;
 
-    Ok((t3, h3))
+            Ok((t3, h3, rolls_used))
+        },
+        McStrategy::Optimal(optimal) => {
+            let t3 = optimal.throw_hand(dices, score_card);
+            let (h3, _) = optimal.best_available_game_hand(&t3, score_card);
+
+            Ok((t3, h3, rules.rolls()))
+        },
+    }
 }
 
-pub fn throw_hand(dices: &mut Dices, available_hands: u16, hands: &Vec<Box<Hand>>) -> Result<Vec<u8>, String> {
-    let t1_code = base7_to_base10(&dices.throw_and_hold(None));
-    let h1 = best_available_hand(Throw::First, t1_code, available_hands, &hands)?;
-    let (_, s1_code, _) = hands[h1].optimal_holds(Throw::First)?.get(&t1_code).unwrap();
+/// Plays a turn by the optimal holds of the most promising hand and returns the final dices
+/// together with the number of rolls used. When rolls can be saved the turn stops early once the
+/// dices score at least what is expected from holding on.
+pub fn throw_hand(dices: &mut Dices, available_hands: u32, rolls: usize, save_rolls: bool, hands: &[Hand]) -> Result<(Vec<u8>, usize), String> {
+    let mut thrown = dices.throw_and_hold(None);
 
-    let t2_code = base7_to_base10(&dices.throw_and_hold(Some(base10_to_base7(*s1_code))));
-    let h2 = best_available_hand(Throw::Second, t2_code, available_hands, &hands)?;
-    let (_, s2_code, _) = hands[h2].optimal_holds(Throw::Second)?.get(&t2_code).unwrap();
+    for throw in 0..rolls - 1 {
+        let rolls_left = rolls - 1 - throw;
+        let t_code = base7_to_base10(&thrown);
+        let hand = &hands[best_available_hand(rolls_left, t_code, available_hands, hands)?];
+        let (_, s_code, expected) = hand.optimal_holds(hand.throw_with_rolls_left(rolls_left))?.get(&t_code).unwrap();
 
-    let t3 = dices.throw_and_hold(Some(base10_to_base7(*s2_code)));
+        if save_rolls && hand.score(&thrown) as f64 >= *expected {
+            return Ok((thrown, throw + 1));
+        }
+        thrown = dices.throw_and_hold(Some(base10_to_base7(*s_code)));
+    }
 
-    Ok(t3)
+    Ok((thrown, rolls))
 }
 
-fn check_hold(dices: &Vec<u8>, hold: &Vec<u8>) -> bool {
+fn check_hold(dices: &[u8], hold: &[u8]) -> bool {
     let mut hold_iter = hold.iter();
     let mut h = if let Some(h) = hold_iter.next() {
         *h
@@ -149,17 +403,17 @@ fn get_hand_choice(available_hands: Vec<u8>) -> u8 {
     }
 }
 
-fn get_dices_input(caption: &str, dices: Option<&Vec<u8>>, hold: Option<&Vec<u8>>) -> Vec<u8> {
+fn get_dices_input(caption: &str, dices: Option<&Vec<u8>>, hold: Option<&Vec<u8>>, n_dices: usize) -> Vec<u8> {
     let stdin = stdin();
     let mut input = String::new();
-    let mut res: Vec<u8> = Vec::with_capacity(5);
+    let mut res: Vec<u8> = Vec::with_capacity(n_dices);
 
-    let (min_dices, max_dices): (usize, usize) = if let Some(_) = dices {
-        (0, 5)
+    let (min_dices, max_dices): (usize, usize) = if dices.is_some() {
+        (0, n_dices)
     } else if let Some(h) = hold {
-        (5 - h.len(), 5 - h.len())
+        (n_dices - h.len(), n_dices - h.len())
     } else {
-        (5, 5)
+        (n_dices, n_dices)
     };
 
     println!("{}", caption);
@@ -209,38 +463,50 @@ fn get_dices_input(caption: &str, dices: Option<&Vec<u8>>, hold: Option<&Vec<u8>
     res
 }
 
-fn print_score_card(human_scores: &HashMap<u8, u16>, mc_scores: &HashMap<u8, u16>, names: &Vec<String>, latest: (u8, u8)) {
+fn print_score_card(human_card: &ScoreCard, mc_card: &ScoreCard, names: &[String], latest: (u8, u8), rules: &Ruleset) {
     let mut human_total: u16 = 0;
     let mut mc_total: u16 = 0;
 
     println!("____________________________________");
     println!("| Player:              |Human|  MC |");
     println!("|==================================|");
-    for i in 0..15u8 {
-        let human_score = format_score_string(&human_scores, i, i==latest.0, &mut human_total);
-        let mc_score = format_score_string(&mc_scores, i, i==latest.1, &mut mc_total);
+    for i in 0..rules.n_hands() as u8 {
+        // Par for an upper hand is the number of dices per face needed to reach the bonus
+        let par = rules.bonus_threshold() / 21;
+        let human_score = format_score_string(human_card.scores(), i, i==latest.0, par, &mut human_total);
+        let mc_score = format_score_string(mc_card.scores(), i, i==latest.1, par, &mut mc_total);
 
         println!("| {:2}. {:16} | {:3} | {:3} |", i+1, names[i as usize], human_score, mc_score);
 
         if i == 5 {
             println!("|==================================|");
             println!("|     {:16} | {:3} | {:3} |", "Sum:", human_total, mc_total);
-            let human_bonus: u16 = if human_total >= 63 {50} else {0};
-            let mc_bonus: u16 = if mc_total >= 63 {50} else {0};
+            let human_bonus = human_card.bonus(rules, rules.bonus()) as u16;
+            let mc_bonus = mc_card.bonus(rules, rules.bonus()) as u16;
             println!("|     {:16} | {:3} | {:3} |", "Bonus:", human_bonus, mc_bonus);
             human_total += human_bonus;
             mc_total += mc_bonus
         }
     }
+    if rules.yahtzee_hand().is_some() {
+        println!("|==================================|");
+        println!("|     {:16} | {:3} | {:3} |", "Yahtzee bonus:", human_card.yahtzee_bonus(), mc_card.yahtzee_bonus());
+        human_total += human_card.yahtzee_bonus();
+        mc_total += mc_card.yahtzee_bonus();
+    }
+    if rules.saved_rolls() {
+        println!("|==================================|");
+        println!("|     {:16} | {:3} | {:3} |", "Saved rolls:", human_card.saved_rolls(), mc_card.saved_rolls());
+    }
     println!("|==================================|");
     println!("     {:16}   {:3}   {:3}\n", "Total:", human_total, mc_total);
 }
 
-fn format_score_string(scores: &HashMap<u8, u16>, pos: u8, is_latest: bool, total: &mut u16) -> ColoredString {
+fn format_score_string(scores: &HashMap<u8, u16>, pos: u8, is_latest: bool, par: u16, total: &mut u16) -> ColoredString {
     let mut formatted_score = match scores.get(&pos) {
         Some(s) => {
             *total += *s;
-            if pos < 6 && *s < (pos as u16 + 1) * 3 {
+            if pos < 6 && *s < (pos as u16 + 1) * par {
                 s.to_string().bright_yellow()
             } else {
                 s.to_string().normal()
@@ -1,54 +1,60 @@
 pub mod rules;
+pub mod ruleset;
+pub mod score_card;
+pub mod scoring;
 
-use crate::dices::Throw;
-use crate::dices::Throw::{First, Second};
-use crate::utils::base10_to_base7;
+use crate::utils::{base10_to_base7, records_in_file, write_records_header};
 use std::collections::HashMap;
 use std::format;
 use std::fs::File;
-use std::io::{BufWriter, Write};
+use std::io::{BufReader, BufWriter, Read, Write};
 use crate::DEBUG_DIR;
 
+/// Thrown dices code to hold length, hold code and expected score
+pub type HoldTable = HashMap<u32, (u8, u32, f64)>;
+
+/// Thrown dices and hold codes to hold length, hits, total score and total squared score
+type MCTable = HashMap<(u32, u32), (u8, f64, f64, f64)>;
+
+/// Optimal hold per thrown dices, one table for every throw followed by a hold
 pub struct OptimalHolds {
-    first: HashMap<u16, (u8, u16, f64)>,
-    second: HashMap<u16, (u8, u16, f64)>,
+    throws: Vec<HoldTable>,
 }
 
 impl OptimalHolds {
-    pub fn new() -> OptimalHolds {
+    pub fn new(rolls: usize) -> OptimalHolds {
         OptimalHolds {
-            first: HashMap::new(),
-            second: HashMap::new(),
+            throws: vec![HashMap::new(); rolls - 1],
         }
     }
 }
 
 pub struct MCHands {
-    first: HashMap<(u16, u16), (u8, f64, f64)>,
-    second: HashMap<(u16, u16), (u8, f64, f64)>,
+    throws: Vec<MCTable>,
     name: String,
 }
 
 impl MCHands {
-    pub fn new() -> MCHands {
+    pub fn new(rolls: usize) -> MCHands {
         MCHands {
-            first: HashMap::new(),
-            second: HashMap::new(),
+            throws: vec![HashMap::new(); rolls - 1],
             name: "mchand".to_string(),
         }
     }
 
-    pub fn update_optimal_holds(&self, opt_holds: [&mut HashMap<u16, (u8, u16, f64)>; 2], min_holds: u8) {
-        let mc = [&self.first, &self.second];
+    pub fn update_optimal_holds(&self, opt_holds: &mut [HoldTable], min_holds: u8) {
+        let mc = &self.throws;
 
-        for throw in 0..2 {
+        for throw in 0..mc.len() {
             opt_holds[throw].clear();
 
-            for ((thrown, hold), (mc_s_len, hits, value)) in mc[throw].iter() {
+            for ((thrown, hold), (mc_s_len, hits, value, _)) in mc[throw].iter() {
                 if *mc_s_len <= min_holds {
                     match opt_holds[throw].get(thrown) {
-                        Some((_, _, score)) => {
-                            if *value / *hits > *score
+                        Some((_, best_hold, score)) => {
+                            // Ties are broken on hold code so the result is independent of map order
+                            let avg = *value / *hits;
+                            if avg > *score || (avg == *score && *hold < *best_hold)
                             {
                                 opt_holds[throw].insert(*thrown, (*mc_s_len, *hold, *value / *hits));
                             }
@@ -62,30 +68,158 @@ impl MCHands {
         }
     }
 
-    pub fn update_scores(&mut self, throw: Throw, t_code: u16, s_code: u16, s_len: u8, score: f64) {
-        let mc: &mut HashMap<(u16, u16), (u8, f64, f64)>;
-        match throw {
-            First => {
-                mc = &mut self.first;
+    pub fn update_scores(&mut self, throw: usize, t_code: u32, s_code: u32, s_len: u8, score: f64) {
+        let mc = &mut self.throws[throw];
+
+        match mc.get(&(t_code, s_code)) {
+            Some((_, hits, value, squares)) => {
+                mc.insert((t_code, s_code), (s_len, *hits + 1.0, *value + score, *squares + score * score));
             }
-            Second => {
-                mc = &mut self.second;
+            None => {
+                mc.insert((t_code, s_code), (s_len, 1.0, score, score * score));
             }
-            _ => return,
         }
+    }
 
-        match mc.get(&(t_code, s_code)) {
-            Some((_, hits, value)) => {
-                mc.insert((t_code, s_code), (s_len, *hits + 1.0, *value + score));
+    /// Number of thrown dices per throw where the best hold is told apart from the runner-up,
+    /// either separated by z standard errors or, when the two are about equally good, with the
+    /// difference known within tolerance. All holds of the thrown dices, given by number of holds
+    /// per thrown dices code, must be played at least min_hits times. The standard error is kept
+    /// from dropping below max_score / hits, as holds never or always scoring show no variance.
+    pub fn decided_throws(&self, n_holds: &HashMap<u32, usize>, min_holds: u8, min_hits: f64, max_score: f64, z: f64, tolerance: f64) -> usize {
+        let mut decided = 0;
+
+        for mc in &self.throws {
+            // Mean and squared standard error of the two best holds per thrown dices
+            let mut best: HashMap<u32, [Option<(f64, f64)>; 2]> = HashMap::new();
+            let mut played: HashMap<u32, usize> = HashMap::new();
+
+            for ((thrown, _), (s_len, hits, value, squares)) in mc.iter() {
+                if *s_len > min_holds {
+                    continue;
+                }
+                if *hits >= min_hits {
+                    *played.entry(*thrown).or_insert(0) += 1;
+                }
+
+                let mean = *value / *hits;
+                let variance = ((*squares / *hits - mean * mean).max(0.0) / (*hits - 1.0).max(1.0))
+                    .max((max_score / *hits).powi(2));
+                let top = best.entry(*thrown).or_insert([None, None]);
+                if top[0].is_none_or(|(m, _)| mean > m) {
+                    top[1] = top[0];
+                    top[0] = Some((mean, variance));
+                } else if top[1].is_none_or(|(m, _)| mean > m) {
+                    top[1] = Some((mean, variance));
+                }
             }
-            None => {
-                mc.insert((t_code, s_code), (s_len, 1.0, score));
+
+            for (thrown, top) in best {
+                if played.get(&thrown) != n_holds.get(&thrown) {
+                    continue;
+                }
+                match top {
+                    [Some((m1, v1)), Some((m2, v2))] => {
+                        let error = (v1 + v2).sqrt();
+                        if m1 - m2 >= z * error || z * error <= tolerance {
+                            decided += 1;
+                        }
+                    }
+                    _ => decided += 1,
+                }
             }
         }
+
+        decided
+    }
+
+    /// Number of times a throw and hold is played
+    pub fn hits(&self, throw: usize, t_code: u32, s_code: u32) -> f64 {
+        self.throws[throw].get(&(t_code, s_code)).map_or(0.0, |(_, hits, _, _)| *hits)
+    }
+
+    /// Adds the hits and total scores saved for a hand to the ones learned so far, so that
+    /// learning can continue and runs from several machines can be merged
+    pub fn load_scores(&mut self, path: &str, name: &str) -> Result<(), String> {
+        let path_name = &format!("{}/{}.{}.bin", path, self.name, name);
+        let mut buf_reader = match File::open(path_name) {
+            Ok(f) => BufReader::new(f),
+            Err(e) => return Err(format!("Error while open file {}: {}", path_name, e)),
+        };
+
+        let mut buf = [0u8; 34];
+        let mut n_records = records_in_file(&mut buf_reader, path_name)?;
+
+        while n_records > 0 {
+            match buf_reader.read_exact(&mut buf) {
+                Ok(()) => {
+                    let throw = buf[0] as usize;
+                    let hold_len = buf[1];
+                    let thrown = u32::from_le_bytes(buf[2..6].try_into().unwrap());
+                    let hold = u32::from_le_bytes(buf[6..10].try_into().unwrap());
+                    let hits = f64::from_le_bytes(buf[10..18].try_into().unwrap());
+                    let score = f64::from_le_bytes(buf[18..26].try_into().unwrap());
+                    let squares = f64::from_le_bytes(buf[26..34].try_into().unwrap());
+
+                    match self.throws.get_mut(throw) {
+                        Some(mc) => {
+                            let entry = mc.entry((thrown, hold)).or_insert((hold_len, 0.0, 0.0, 0.0));
+                            entry.1 += hits;
+                            entry.2 += score;
+                            entry.3 += squares;
+                        }
+                        None => return Err(format!("Error, file {} is learned for more rolls", path_name)),
+                    };
+                }
+                Err(e) => {
+                    return Err(format!("Error while reading from file {}: {}", path_name, e));
+                }
+            }
+            n_records -= 1;
+        }
+        Ok(())
+    }
+
+    pub fn save_scores(&self, path: &str, name: &str) -> Result<(), String> {
+        let score_vec = self.throws.iter().collect::<Vec<&MCTable>>();
+
+        let path_name = &format!("{}/{}.{}.bin", path, self.name, name);
+        let mut buf_writer = match File::create(path_name) {
+            Ok(f) => BufWriter::new(f),
+            Err(e) => return Err(format!("Error while open/create file {}: {}", path_name, e)),
+        };
+
+        write_records_header(&mut buf_writer, &score_vec, path_name)?;
+
+        let mut buf = [0u8; 34];
+        for (throw, table) in score_vec.iter().enumerate() {
+            // Keys are written in order so that seeded runs give byte-identical files
+            let mut keys: Vec<&(u32, u32)> = table.keys().collect();
+            keys.sort();
+
+            for key in keys {
+                let (hold_len, hits, score, squares) = table.get(key).unwrap();
+                buf[0] = throw as u8;
+                buf[1] = *hold_len;
+                buf[2..6].copy_from_slice(&key.0.to_le_bytes());
+                buf[6..10].copy_from_slice(&key.1.to_le_bytes());
+                buf[10..18].copy_from_slice(&hits.to_le_bytes());
+                buf[18..26].copy_from_slice(&score.to_le_bytes());
+                buf[26..34].copy_from_slice(&squares.to_le_bytes());
+
+                if let Err(e) = buf_writer.write_all(&buf) {
+                    return Err(format!("Error while writing to file {}: {}", path_name, e));
+                }
+            }
+        }
+        if let Err(e) = buf_writer.flush() {
+            return Err(format!("Error while writing to file {}: {}", path_name, e));
+        }
+        Ok(())
     }
 
     pub fn debug_scores(&self, path: &str, name: &str) -> Result<(), String> {
-        let score_arr = [&self.first, &self.second];
+        let score_arr = &self.throws;
 
         let path_name = &format!("{}/{}/{}.{}.txt", path, DEBUG_DIR, self.name, name);
         let mut buf_writer = match File::create(path_name) {
@@ -93,16 +227,16 @@ impl MCHands {
             Err(e) => return Err(format!("Error while open/create file {}: {}", path_name, e)),
         };
 
-        for throw in 0..score_arr.len() {
-            let mut keys: Vec<(u16, u16)> = score_arr[throw]
+        for (throw, table) in score_arr.iter().enumerate() {
+            let mut keys: Vec<(u32, u32)> = table
                 .keys()
-                .map(|k| *k)
-                .collect::<Vec<(u16, u16)>>();
-            keys.sort_by_key(|k| k.0 as u64 * 100000 + k.1 as u64);
+                .copied()
+                .collect::<Vec<(u32, u32)>>();
+            keys.sort_by_key(|k| k.0 as u64 * 1000000 + k.1 as u64);
 
-            // HashMap<(u16, u16), (u8, f64, f64)>
+            // HashMap<(u32, u32), (u8, f64, f64, f64)>
             for (thrown, hold) in keys {
-                let (hold_len, hits, score) = score_arr[throw].get(&(thrown, hold)).unwrap();
+                let (hold_len, hits, score, _) = table.get(&(thrown, hold)).unwrap();
                 let t_vec = base10_to_base7(thrown);
                 let s_vec = base10_to_base7(hold);
 
@@ -0,0 +1,423 @@
+use std::fs::read_to_string;
+use std::path::Path;
+use std::sync::Arc;
+use serde::Deserialize;
+use crate::dices::DiceModel;
+use crate::score_box::rules::Hand;
+use crate::score_box::scoring::*;
+use crate::utils::check_path_create_folder;
+
+/// Number of rolls per turn unless set otherwise
+const DEFAULT_ROLLS: usize = 3;
+
+/// Most rolls per turn supported
+const MAX_ROLLS: usize = 6;
+
+/// Saved rolls that hold tables are learned for on top of the rolls per turn, turns with even
+/// more saved rolls use the tables for the most rolls left
+const LEARNED_SAVED_ROLLS: usize = 3;
+
+/// Most boxes on a score card, weights of more hands can't be learned as the grid of weight
+/// tunings is counted in u32
+pub const MAX_BOXES: usize = 20;
+
+/// Ruleset file listing all boxes in score card order. The score card starts with the upper
+/// section, one sum_of_face box for each face ones to sixes, as the bonus and joker rules count
+/// on it, e.g.
+///
+/// ```toml
+/// name = "house"
+/// dices = 5
+/// rolls = 3
+/// bonus_threshold = 63
+/// bonus = 50
+///
+/// [[boxes]]
+/// name = "ones"
+/// pattern = "sum_of_face"
+/// face = 1
+///
+/// # ... twos to sixes
+///
+/// [[boxes]]
+/// name = "three_of_a_kind"
+/// pattern = "n_of_a_kind"
+/// n = 3
+///
+/// [[boxes]]
+/// name = "yatzy"
+/// pattern = "n_of_a_kind"
+/// n = 5
+/// score = 50
+/// ```
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RulesetFile {
+    /// Also the folder of the models, letters, digits, _ or - and not a built-in ruleset name
+    name: String,
+    dices: usize,
+    rolls: usize,
+    #[serde(default)]
+    saved_rolls: bool,
+    bonus_threshold: u16,
+    bonus: u32,
+    boxes: Vec<BoxFile>,
+}
+
+/// Box in a ruleset file, scoring the pattern or the fixed value score whenever the pattern scores
+#[derive(Deserialize)]
+struct BoxFile {
+    name: String,
+    #[serde(flatten)]
+    pattern: Pattern,
+    score: Option<u16>,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "pattern", rename_all = "snake_case")]
+enum Pattern {
+    SumOfFace { face: u8 },
+    NOfAKind { n: u8 },
+    Pairs { n: u8 },
+    FixedStraight { from: u8, to: u8 },
+    AnyRun { len: u8 },
+    FullHouse,
+    Chance,
+}
+
+/// A game variant given by the number of dices and a registry of the boxes on the score card,
+/// upper section boxes (ones to sixes) always come first.
+#[derive(Clone)]
+pub struct Ruleset {
+    name: String,
+    n_dices: usize,
+    boxes: Vec<ScoreBox>,
+    bonus_threshold: u16,
+    bonus: u32,
+    yahtzee_hand: Option<u8>,
+    forced_order: bool,
+    rolls: usize,
+    saved_rolls: bool,
+    dice_model: DiceModel,
+}
+
+impl Ruleset {
+    /// Ruleset with the upper section boxes registered, lower section boxes are registered in
+    /// score card order after
+    pub fn new(name: &str, n_dices: usize, bonus_threshold: u16, bonus: u32) -> Ruleset {
+        let mut rules = Ruleset::without_boxes(name, n_dices, bonus_threshold, bonus);
+        (1..7).for_each(|face| rules.register(Arc::new(Upper { face })));
+
+        rules
+    }
+
+    fn without_boxes(name: &str, n_dices: usize, bonus_threshold: u16, bonus: u32) -> Ruleset {
+        Ruleset {
+            name: String::from(name),
+            n_dices,
+            boxes: Vec::new(),
+            bonus_threshold,
+            bonus,
+            yahtzee_hand: None,
+            forced_order: false,
+            rolls: DEFAULT_ROLLS,
+            saved_rolls: false,
+            dice_model: DiceModel::fair(),
+        }
+    }
+
+    pub fn register(&mut self, score_box: ScoreBox) {
+        self.boxes.push(score_box);
+    }
+
+    /// Registers the Yahtzee box, which enables Yahtzee bonuses and joker rules once filled
+    pub fn register_yahtzee(&mut self, score_box: ScoreBox) {
+        self.yahtzee_hand = Some(self.boxes.len() as u8);
+        self.register(score_box);
+    }
+
+    /// Scandinavian Yatzy with five dices and fifteen hands
+    pub fn scandinavian() -> Ruleset {
+        let mut rules = Ruleset::new("scandinavian", 5, 63, 50);
+        rules.register(Arc::new(Pairs { n: 1 }));
+        rules.register(Arc::new(Pairs { n: 2 }));
+        rules.register(Arc::new(OfAKind { n: 3 }));
+        rules.register(Arc::new(OfAKind { n: 4 }));
+        rules.register(Arc::new(Straight { name: String::from("small_straight"), faces: 1..=5, score: 15 }));
+        rules.register(Arc::new(Straight { name: String::from("large_straight"), faces: 2..=6, score: 20 }));
+        rules.register(Arc::new(FullHouse));
+        rules.register(Arc::new(Chance));
+        rules.register(Arc::new(Yatzy { name: String::from("yatzy"), score: 50 }));
+
+        rules
+    }
+
+    /// Maxi Yatzy with six dices, twenty hands and saved rolls
+    pub fn maxi() -> Ruleset {
+        let mut rules = Ruleset::new("maxi", 6, 84, 50);
+        rules.saved_rolls = true;
+        rules.register(Arc::new(Pairs { n: 1 }));
+        rules.register(Arc::new(Pairs { n: 2 }));
+        rules.register(Arc::new(Pairs { n: 3 }));
+        rules.register(Arc::new(OfAKind { n: 3 }));
+        rules.register(Arc::new(OfAKind { n: 4 }));
+        rules.register(Arc::new(OfAKind { n: 5 }));
+        rules.register(Arc::new(Straight { name: String::from("small_straight"), faces: 1..=5, score: 15 }));
+        rules.register(Arc::new(Straight { name: String::from("large_straight"), faces: 2..=6, score: 20 }));
+        rules.register(Arc::new(Straight { name: String::from("full_straight"), faces: 1..=6, score: 21 }));
+        rules.register(Arc::new(FullHouse));
+        rules.register(Arc::new(Castle));
+        rules.register(Arc::new(Tower));
+        rules.register(Arc::new(Chance));
+        rules.register(Arc::new(Yatzy { name: String::from("maxi_yatzy"), score: 100 }));
+
+        rules
+    }
+
+    /// American Yahtzee with five dices and thirteen hands, straights of any run, three and four
+    /// of a kind scored as the sum of all dices, Yahtzee bonuses and joker rules
+    pub fn yahtzee() -> Ruleset {
+        let mut rules = Ruleset::new("yahtzee", 5, 63, 35);
+        rules.register(Arc::new(TotalOfAKind { n: 3 }));
+        rules.register(Arc::new(TotalOfAKind { n: 4 }));
+        rules.register(Arc::new(FixedFullHouse { score: 25 }));
+        rules.register(Arc::new(AnyStraight { name: String::from("small_straight"), len: 4, score: 30 }));
+        rules.register(Arc::new(AnyStraight { name: String::from("large_straight"), len: 5, score: 40 }));
+        rules.register_yahtzee(Arc::new(Yatzy { name: String::from("yahtzee"), score: 50 }));
+        rules.register(Arc::new(Chance));
+
+        rules
+    }
+
+    /// Ruleset by name or from a ruleset file
+    pub fn from_arg(arg: &str) -> Result<Ruleset, String> {
+        match arg {
+            "scandinavian" => Ok(Ruleset::scandinavian()),
+            "maxi" => Ok(Ruleset::maxi()),
+            "yahtzee" => Ok(Ruleset::yahtzee()),
+            _ if Path::new(arg).is_file() => Ruleset::from_file(arg),
+            _ => Err(format!("Error, unknown ruleset {}", arg)),
+        }
+    }
+
+    pub fn from_file(path_name: &str) -> Result<Ruleset, String> {
+        let content = match read_to_string(path_name) {
+            Ok(c) => c,
+            Err(e) => return Err(format!("Error while reading from file {}: {}", path_name, e)),
+        };
+        let file: RulesetFile = match toml::from_str(&content) {
+            Ok(f) => f,
+            Err(e) => return Err(format!("Error while parsing ruleset file {}: {}", path_name, e)),
+        };
+
+        // The name is the folder of the models, it must not share the folder of a built-in
+        // ruleset or reach outside the path, dots are kept for the rolls and dices suffixes
+        if ["scandinavian", "maxi", "yahtzee"].contains(&file.name.as_str()) {
+            return Err(format!("Error, ruleset file {} can't use the name of the built-in ruleset {}", path_name, file.name));
+        }
+        if file.name.is_empty() || !file.name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+            return Err(format!("Error, ruleset name {:?} in file {} must be letters, digits, _ or -", file.name, path_name));
+        }
+        if file.dices < 1 || file.dices > 6 {
+            return Err(format!("Error, ruleset {} must have between 1 and 6 dices", file.name));
+        }
+        if file.boxes.len() > MAX_BOXES {
+            return Err(format!("Error, ruleset {} can have at most {} boxes", file.name, MAX_BOXES));
+        }
+        let upper_section = file.boxes.len() >= 6 && file.boxes.iter().enumerate().all(|(i, b)| match b.pattern {
+            Pattern::SumOfFace { face } => face as usize == i + 1,
+            _ => i >= 6,
+        });
+        if !upper_section {
+            return Err(format!("Error, ruleset {} must start with sum_of_face boxes for faces 1 to 6 in order, and have no other", file.name));
+        }
+
+        let n_dices = file.dices as u8;
+        let mut rules = Ruleset::without_boxes(&file.name, file.dices, file.bonus_threshold, file.bonus);
+        rules.set_rolls(file.rolls)?;
+        rules.saved_rolls = file.saved_rolls;
+        for b in file.boxes {
+            if rules.boxes.iter().any(|r| r.name() == b.name) {
+                return Err(format!("Error, box {} defined more than once in ruleset {}", b.name, file.name));
+            }
+            if matches!(b.pattern, Pattern::AnyRun { .. }) && b.score.is_none() {
+                return Err(format!("Error, box {} with pattern any_run must have a score", b.name));
+            }
+
+            let pattern: ScoreBox = match b.pattern {
+                Pattern::SumOfFace { face } if (1..=6).contains(&face) => Arc::new(Upper { face }),
+                Pattern::NOfAKind { n } if n >= 1 && n <= n_dices => Arc::new(OfAKind { n }),
+                Pattern::Pairs { n } if n >= 1 && 2 * n <= n_dices => Arc::new(Pairs { n }),
+                Pattern::FixedStraight { from, to } if from >= 1 && from <= to && to <= 6 && to - from < n_dices => {
+                    Arc::new(Straight { name: b.name.clone(), faces: from..=to, score: (from..=to).map(|f| f as u16).sum() })
+                },
+                Pattern::AnyRun { len } if len >= 1 && len <= n_dices => {
+                    Arc::new(AnyStraight { name: b.name.clone(), len, score: b.score.unwrap_or(0) })
+                },
+                Pattern::FullHouse if n_dices >= 5 => Arc::new(FullHouse),
+                Pattern::Chance => Arc::new(Chance),
+                _ => return Err(format!("Error, box {} has a pattern not possible with {} dices", b.name, n_dices)),
+            };
+
+            rules.register(Arc::new(PatternBox { name: b.name, pattern, score: b.score }));
+        }
+
+        Ok(rules)
+    }
+
+    pub fn n_dices(&self) -> usize {
+        self.n_dices
+    }
+
+    pub fn n_hands(&self) -> usize {
+        self.boxes.len()
+    }
+
+    /// Bitmask with all hands on the score card available
+    pub fn all_hands(&self) -> u32 {
+        (1u32 << self.n_hands()) - 1
+    }
+
+    /// Sum of the upper section needed to get the bonus
+    pub fn bonus_threshold(&self) -> u16 {
+        self.bonus_threshold
+    }
+
+    /// Changes the upper section bonus rule, i.e. the sum needed and the bonus given
+    pub fn set_bonus(&mut self, bonus_threshold: u16, bonus: u32) {
+        self.bonus_threshold = bonus_threshold;
+        self.bonus = bonus;
+    }
+
+    /// Bonus for reaching the bonus threshold in the upper section
+    pub fn bonus(&self) -> u32 {
+        self.bonus
+    }
+
+    /// Position of the Yahtzee hand when the ruleset uses Yahtzee bonuses and joker rules
+    pub fn yahtzee_hand(&self) -> Option<u8> {
+        self.yahtzee_hand
+    }
+
+    pub fn set_rolls(&mut self, rolls: usize) -> Result<(), String> {
+        if !(1..=MAX_ROLLS).contains(&rolls) {
+            return Err(format!("Error, number of rolls must be between 1 and {}", MAX_ROLLS));
+        }
+        self.rolls = rolls;
+        Ok(())
+    }
+
+    /// Number of rolls per turn
+    pub fn rolls(&self) -> usize {
+        self.rolls
+    }
+
+    /// Whether rolls not used in a turn are saved for later turns
+    pub fn saved_rolls(&self) -> bool {
+        self.saved_rolls
+    }
+
+    /// Number of rolls hold tables are learned for
+    pub fn hold_rolls(&self) -> usize {
+        if self.saved_rolls {self.rolls + LEARNED_SAVED_ROLLS} else {self.rolls}
+    }
+
+    /// Dice model used in learning and simulation
+    pub fn set_dice_model(&mut self, dice_model: DiceModel) {
+        self.dice_model = dice_model;
+    }
+
+    pub fn dice_model(&self) -> &DiceModel {
+        &self.dice_model
+    }
+
+    /// Forced order mode, i.e. hands must be scored top-down in score card order
+    pub fn set_forced_order(&mut self, forced_order: bool) {
+        self.forced_order = forced_order;
+    }
+
+    pub fn forced_order(&self) -> bool {
+        self.forced_order
+    }
+
+    /// Hands that may be scored next, in forced order mode only the topmost available hand
+    pub fn open_hands(&self, available_hands: u32) -> u32 {
+        if self.forced_order {
+            available_hands & available_hands.wrapping_neg()
+        } else {
+            available_hands
+        }
+    }
+
+    /// Whether thrown dices are a Yahtzee that must be played as a joker, i.e. the Yahtzee hand
+    /// is already filled
+    pub fn is_joker(&self, thrown: &[u8], available_hands: u32) -> bool {
+        match self.yahtzee_hand() {
+            Some(y) => available_hands & (1 << y) == 0 && thrown.iter().all(|&d| d == thrown[0]),
+            None => false,
+        }
+    }
+
+    /// Hands that thrown dices may be scored in. A joker must be scored in the upper hand of its
+    /// face if available (forced joker), else in any available lower hand (free joker) and only
+    /// when all lower hands are filled in any available upper hand. In forced order mode only the
+    /// topmost available hand is allowed.
+    pub fn allowed_hands(&self, thrown: &[u8], available_hands: u32) -> u32 {
+        if self.forced_order || !self.is_joker(thrown, available_hands) {
+            return self.open_hands(available_hands);
+        }
+
+        let face = 1u32 << (thrown[0] - 1);
+        if available_hands & face > 0 {
+            face
+        } else if available_hands & !0b111111 > 0 {
+            available_hands & !0b111111
+        } else {
+            available_hands
+        }
+    }
+
+    /// Score of thrown dices in a hand, a joker scores full house and straights in full
+    pub fn score(&self, hand: u8, thrown: &[u8], available_hands: u32) -> u16 {
+        let score_box = &self.boxes[hand as usize];
+        let score = match score_box.joker_score() {
+            Some(s) if self.is_joker(thrown, available_hands) => s,
+            _ => score_box.score(thrown),
+        };
+
+        score as u16
+    }
+
+    /// Registered boxes in score card order
+    pub fn boxes(&self) -> &Vec<ScoreBox> {
+        &self.boxes
+    }
+
+    /// Hands in score card order, the id of each hand is its position on the score card
+    pub fn hands(&self) -> Vec<Hand> {
+        self.boxes
+            .iter()
+            .enumerate()
+            .map(|(id, b)| Hand::new(b.clone(), id, self.n_dices, self.hold_rolls(), &self.dice_model))
+            .collect()
+    }
+
+    /// Path to models for this ruleset, models for rulesets other than the default, for
+    /// another number of rolls and for dices that are not fair are kept in a folder of their own
+    pub fn model_path(&self, path: &str) -> Result<String, String> {
+        let mut folder = if self.rolls == DEFAULT_ROLLS {
+            self.name.clone()
+        } else {
+            format!("{}.{}_rolls", self.name, self.rolls)
+        };
+        if !self.dice_model.is_fair() {
+            folder = format!("{}.{}_dices", folder, self.dice_model.name());
+        }
+        if folder == Ruleset::scandinavian().name {
+            return Ok(path.to_string());
+        }
+
+        check_path_create_folder(path, Some(&folder))?;
+        Ok(format!("{}/{}", path, folder))
+    }
+}
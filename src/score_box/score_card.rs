@@ -0,0 +1,104 @@
+use std::collections::HashMap;
+use crate::score_box::ruleset::Ruleset;
+
+/// Yahtzee bonus for every further Yahtzee once the Yahtzee hand is scored with 50
+pub const YAHTZEE_BONUS: u16 = 100;
+
+/// Score card of one player during a game, the scored hands and the hands still available
+/// (same bitmask as everywhere else, bit i set if hand i is available)
+pub struct ScoreCard {
+    scores: HashMap<u8, u16>,
+    available_hands: u32,
+    yahtzee_bonus: u16,
+    saved_rolls: usize,
+}
+
+impl ScoreCard {
+    pub fn new(rules: &Ruleset) -> ScoreCard {
+        ScoreCard {
+            scores: HashMap::new(),
+            available_hands: rules.all_hands(),
+            yahtzee_bonus: 0,
+            saved_rolls: 0,
+        }
+    }
+
+    pub fn scores(&self) -> &HashMap<u8, u16> {
+        &self.scores
+    }
+
+    pub fn available_hands(&self) -> u32 {
+        self.available_hands
+    }
+
+    pub fn yahtzee_bonus(&self) -> u16 {
+        self.yahtzee_bonus
+    }
+
+    pub fn saved_rolls(&self) -> usize {
+        self.saved_rolls
+    }
+
+    /// Rolls available in the next turn, including any saved rolls
+    pub fn turn_rolls(&self, rules: &Ruleset) -> usize {
+        rules.rolls() + self.saved_rolls
+    }
+
+    /// Saves the rolls not used in a turn for later turns, if the ruleset allows
+    pub fn save_rolls(&mut self, rules: &Ruleset, rolls_used: usize) {
+        if rules.saved_rolls() {
+            self.saved_rolls = self.turn_rolls(rules) - rolls_used;
+        }
+    }
+
+    /// Sum of the upper section hands, ones to sixes
+    pub fn upper_sum(&self) -> u16 {
+        self.scores.iter().filter(|&(&h, _)| h < 6).map(|(_, &s)| s).sum()
+    }
+
+    /// Whether the upper section bonus is still to be won, not yet reached but within reach of
+    /// the upper hands still open
+    pub fn bonus_open(&self, rules: &Ruleset) -> bool {
+        let upper_sum = self.upper_sum();
+        let max_left: u16 = (0..6u16)
+            .filter(|&h| self.available_hands & (1 << h) != 0)
+            .map(|h| (h + 1) * rules.n_dices() as u16)
+            .sum();
+
+        upper_sum < rules.bonus_threshold() && upper_sum + max_left >= rules.bonus_threshold()
+    }
+
+    /// Whether the Yahtzee hand is scored with 50, making further Yahtzees worth a bonus
+    pub fn yahtzee_scored(&self, rules: &Ruleset) -> bool {
+        rules
+            .yahtzee_hand()
+            .is_some_and(|y| self.scores.get(&y).is_some_and(|&s| s > 0))
+    }
+
+    /// Hands the thrown dices may be scored in
+    pub fn allowed_hands(&self, rules: &Ruleset, thrown: &[u8]) -> u32 {
+        rules.allowed_hands(thrown, self.available_hands)
+    }
+
+    /// Scores thrown dices in a hand, including any Yahtzee bonus, and returns the hand score
+    pub fn score(&mut self, rules: &Ruleset, hand: u8, thrown: &[u8]) -> u16 {
+        let score = rules.score(hand, thrown, self.available_hands);
+        if self.yahtzee_scored(rules) && rules.is_joker(thrown, self.available_hands) {
+            self.yahtzee_bonus += YAHTZEE_BONUS;
+        }
+
+        self.scores.insert(hand, score);
+        self.available_hands -= 1 << hand;
+        score
+    }
+
+    /// Bonus for the upper section, given the bonus played for
+    pub fn bonus(&self, rules: &Ruleset, bonus: u32) -> u32 {
+        if self.upper_sum() >= rules.bonus_threshold() {bonus} else {0}
+    }
+
+    /// Sum of all scored hands and Yahtzee bonuses, excluding the upper section bonus
+    pub fn total(&self) -> u16 {
+        self.scores.values().sum::<u16>() + self.yahtzee_bonus
+    }
+}
@@ -0,0 +1,383 @@
+use std::ops::RangeInclusive;
+use std::sync::Arc;
+
+/// Scoring rule of a box on the score card. Implement to define custom boxes and register them
+/// in a ruleset, learning, loading, exporting and playing all go through the ruleset registry.
+pub trait ScoringRule: Send + Sync {
+    /// Name of the box, also used in model file names
+    fn name(&self) -> String;
+
+    /// Highest possible score with the given number of dices
+    fn max_score(&self, n_dices: u8) -> u16;
+
+    /// Largest number of dices that are worth holding for the box
+    fn min_holds(&self, n_dices: u8) -> u8 {
+        n_dices
+    }
+
+    /// Score of sorted dices in the box
+    fn score(&self, dices: &[u8]) -> f32;
+
+    /// Score when used as a joker, i.e. scoring a Yahtzee in a lower box once the Yahtzee box
+    /// is filled, None if the box scores as usual
+    fn joker_score(&self) -> Option<f32> {
+        None
+    }
+}
+
+pub type ScoreBox = Arc<dyn ScoringRule>;
+
+/// Number of dices per face, ones first
+fn groups(dices: &[u8]) -> [u8; 6] {
+    let mut groups: [u8; 6] = [0; 6];
+    for dice in dices {
+        groups[*dice as usize - 1] += 1;
+    }
+
+    groups
+}
+
+/// Upper section box scoring the sum of all dices showing the face
+pub struct Upper {
+    pub face: u8,
+}
+
+impl ScoringRule for Upper {
+    fn name(&self) -> String {
+        let names = ["ones", "twos", "threes", "fours", "fives", "sixes"];
+        String::from(names[self.face as usize - 1])
+    }
+
+    fn max_score(&self, n_dices: u8) -> u16 {
+        self.face as u16 * n_dices as u16
+    }
+
+    fn score(&self, dices: &[u8]) -> f32 {
+        (dices.iter().filter(|&&d| d == self.face).count() as u8 * self.face) as f32
+    }
+}
+
+/// The highest pairs of different faces, scoring the sum of the paired dices
+pub struct Pairs {
+    pub n: u8,
+}
+
+impl ScoringRule for Pairs {
+    fn name(&self) -> String {
+        match self.n {
+            1 => String::from("one_pair"),
+            2 => String::from("two_pairs"),
+            3 => String::from("three_pairs"),
+            n => format!("{}_pairs", n),
+        }
+    }
+
+    fn max_score(&self, _n_dices: u8) -> u16 {
+        (0..self.n as u16).map(|i| 2 * (6 - i)).sum()
+    }
+
+    fn min_holds(&self, n_dices: u8) -> u8 {
+        (2 * self.n).min(n_dices)
+    }
+
+    fn score(&self, dices: &[u8]) -> f32 {
+        let groups = groups(dices);
+        let pairs: Vec<u8> = (1..7u8).rev().filter(|&d| groups[d as usize - 1] > 1).collect();
+
+        let res = if pairs.len() >= self.n as usize {
+            pairs.iter().take(self.n as usize).map(|p| p * 2).sum::<u8>()
+        } else {
+            0
+        };
+
+        res as f32
+    }
+}
+
+/// N dices of the highest possible face, scoring the sum of those dices
+pub struct OfAKind {
+    pub n: u8,
+}
+
+impl ScoringRule for OfAKind {
+    fn name(&self) -> String {
+        of_a_kind_name(self.n)
+    }
+
+    fn max_score(&self, _n_dices: u8) -> u16 {
+        6 * self.n as u16
+    }
+
+    fn min_holds(&self, _n_dices: u8) -> u8 {
+        self.n
+    }
+
+    fn score(&self, dices: &[u8]) -> f32 {
+        let groups = groups(dices);
+        let face = (1..7u8).rev().find(|&d| groups[d as usize - 1] >= self.n).unwrap_or(0);
+
+        (face * self.n) as f32
+    }
+}
+
+/// N dices of any face, scoring the sum of all dices
+pub struct TotalOfAKind {
+    pub n: u8,
+}
+
+impl ScoringRule for TotalOfAKind {
+    fn name(&self) -> String {
+        of_a_kind_name(self.n)
+    }
+
+    fn max_score(&self, n_dices: u8) -> u16 {
+        6 * n_dices as u16
+    }
+
+    fn score(&self, dices: &[u8]) -> f32 {
+        let score: u8 = if groups(dices).iter().any(|&g| g >= self.n) {dices.iter().sum()} else {0};
+
+        score as f32
+    }
+}
+
+fn of_a_kind_name(n: u8) -> String {
+    let names = ["one", "two", "three", "four", "five", "six"];
+    format!("{}_of_a_kind", names[n as usize - 1])
+}
+
+/// All faces in a fixed range, scoring a fixed value
+pub struct Straight {
+    pub name: String,
+    pub faces: RangeInclusive<u8>,
+    pub score: u16,
+}
+
+impl ScoringRule for Straight {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn max_score(&self, _n_dices: u8) -> u16 {
+        self.score
+    }
+
+    fn score(&self, dices: &[u8]) -> f32 {
+        if self.faces.clone().all(|d| dices.contains(&d)) {self.score as f32} else {0.0}
+    }
+}
+
+/// A run of consecutive faces starting anywhere, scoring a fixed value, also as a joker
+pub struct AnyStraight {
+    pub name: String,
+    pub len: u8,
+    pub score: u16,
+}
+
+impl ScoringRule for AnyStraight {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn max_score(&self, _n_dices: u8) -> u16 {
+        self.score
+    }
+
+    fn score(&self, dices: &[u8]) -> f32 {
+        let any = (1..8 - self.len).any(|s| (s..s + self.len).all(|d| dices.contains(&d)));
+
+        if any {self.score as f32} else {0.0}
+    }
+
+    fn joker_score(&self) -> Option<f32> {
+        Some(self.score as f32)
+    }
+}
+
+/// Three dices of one face and two of another, scoring the sum of those dices
+pub struct FullHouse;
+
+impl ScoringRule for FullHouse {
+    fn name(&self) -> String {
+        String::from("full_house")
+    }
+
+    fn max_score(&self, _n_dices: u8) -> u16 {
+        28
+    }
+
+    fn score(&self, dices: &[u8]) -> f32 {
+        let groups = groups(dices);
+
+        let mut triple: u8 = 0;
+        let mut pair: u8 = 0;
+        for dice in (1..7).rev() {
+            if groups[dice - 1] > 2 && triple == 0 {
+                triple = dice as u8;
+            } else if groups[dice - 1] > 1 && pair == 0 {
+                pair = dice as u8;
+            }
+        }
+
+        let res = if triple > 0 && pair > 0 {
+            triple * 3 + pair * 2
+        } else {
+            0
+        };
+
+        res as f32
+    }
+}
+
+/// Exactly three dices of one face and two of another, scoring a fixed value, also as a joker
+pub struct FixedFullHouse {
+    pub score: u16,
+}
+
+impl ScoringRule for FixedFullHouse {
+    fn name(&self) -> String {
+        String::from("full_house")
+    }
+
+    fn max_score(&self, _n_dices: u8) -> u16 {
+        self.score
+    }
+
+    fn score(&self, dices: &[u8]) -> f32 {
+        let groups = groups(dices);
+
+        if groups.contains(&3) && groups.contains(&2) {self.score as f32} else {0.0}
+    }
+
+    fn joker_score(&self) -> Option<f32> {
+        Some(self.score as f32)
+    }
+}
+
+/// Two triples of different faces, scoring the sum of all dices
+pub struct Castle;
+
+impl ScoringRule for Castle {
+    fn name(&self) -> String {
+        String::from("castle")
+    }
+
+    fn max_score(&self, _n_dices: u8) -> u16 {
+        33
+    }
+
+    fn score(&self, dices: &[u8]) -> f32 {
+        let groups = groups(dices);
+        let triples: Vec<u8> = (1..7u8).filter(|&d| groups[d as usize - 1] > 2).collect();
+
+        let res = if triples.len() > 1 {
+            triples.iter().map(|t| t * 3).sum::<u8>()
+        } else {
+            0
+        };
+
+        res as f32
+    }
+}
+
+/// Four dices of one face and two of another, scoring the sum of all dices
+pub struct Tower;
+
+impl ScoringRule for Tower {
+    fn name(&self) -> String {
+        String::from("tower")
+    }
+
+    fn max_score(&self, _n_dices: u8) -> u16 {
+        34
+    }
+
+    fn score(&self, dices: &[u8]) -> f32 {
+        let groups = groups(dices);
+
+        let mut quad: u8 = 0;
+        let mut pair: u8 = 0;
+        for dice in (1..7).rev() {
+            if groups[dice - 1] > 3 && quad == 0 {
+                quad = dice as u8;
+            } else if groups[dice - 1] > 1 && pair == 0 {
+                pair = dice as u8;
+            }
+        }
+
+        let res = if quad > 0 && pair > 0 {
+            quad * 4 + pair * 2
+        } else {
+            0
+        };
+
+        res as f32
+    }
+}
+
+/// Any dices, scoring the sum of all dices
+pub struct Chance;
+
+impl ScoringRule for Chance {
+    fn name(&self) -> String {
+        String::from("chance")
+    }
+
+    fn max_score(&self, n_dices: u8) -> u16 {
+        6 * n_dices as u16
+    }
+
+    fn score(&self, dices: &[u8]) -> f32 {
+        dices.iter().sum::<u8>() as f32
+    }
+}
+
+/// All dices of the same face, scoring a fixed value
+pub struct Yatzy {
+    pub name: String,
+    pub score: u16,
+}
+
+impl ScoringRule for Yatzy {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn max_score(&self, _n_dices: u8) -> u16 {
+        self.score
+    }
+
+    fn score(&self, dices: &[u8]) -> f32 {
+        if dices.iter().all(|&d| d == dices[0]) {self.score as f32} else {0.0}
+    }
+}
+
+/// Box defined in a ruleset file, a built-in scoring pattern under a name of its own and
+/// optionally scoring a fixed value whenever the pattern scores
+pub struct PatternBox {
+    pub name: String,
+    pub pattern: ScoreBox,
+    pub score: Option<u16>,
+}
+
+impl ScoringRule for PatternBox {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn max_score(&self, n_dices: u8) -> u16 {
+        self.score.unwrap_or(self.pattern.max_score(n_dices))
+    }
+
+    fn min_holds(&self, n_dices: u8) -> u8 {
+        self.pattern.min_holds(n_dices)
+    }
+
+    fn score(&self, dices: &[u8]) -> f32 {
+        let score = self.pattern.score(dices);
+        match self.score {
+            Some(s) if score > 0.0 => s as f32,
+            _ => score,
+        }
+    }
+}
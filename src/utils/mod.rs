@@ -26,7 +26,7 @@ pub fn records_in_file(buf_reader: &mut BufReader<File>, context: &str) -> Resul
     let mut buf = [0u8;8];
     match buf_reader.read_exact(&mut buf) {
         Ok(()) => Ok(u64::from_le_bytes(buf)),
-        Err(e) => return Err(format!("Error while reading from file {}: {}", context, e)),
+        Err(e) => Err(format!("Error while reading from file {}: {}", context, e)),
     }
 }
 
@@ -64,23 +64,23 @@ pub fn available_threads() -> usize {
         .get()
 }
 
-pub fn base7_to_base10(b7: &Vec<u8>) -> u16 {
+pub fn base7_to_base10(b7: &[u8]) -> u32 {
     let length = b7.len() as u32;
-    let mut res: u16 = 0;
+    let mut res: u32 = 0;
 
     if length > 0 {
         for (i, v) in b7
             .iter()
             .enumerate()
-            .map(|x| (length - x.0 as u32 - 1, *x.1 as u16))
+            .map(|x| (length - x.0 as u32 - 1, *x.1 as u32))
         {
-            res += u16::pow(7, i) * v;
+            res += u32::pow(7, i) * v;
         }
     }
     res
 }
 
-pub fn base3_to_base10(b3: &Vec<u8>) -> u32 {
+pub fn base3_to_base10(b3: &[u8]) -> u32 {
     let length = b3.len() as u32;
     let mut res: u32 = 0;
 
@@ -96,7 +96,7 @@ pub fn base3_to_base10(b3: &Vec<u8>) -> u32 {
     res
 }
 
-pub fn base10_to_base7(b10: u16) -> Vec<u8> {
+pub fn base10_to_base7(b10: u32) -> Vec<u8> {
     let mut d = b10 / 7;
     let mut r = b10 % 7;
     let mut res: Vec<u8> = Vec::new();
@@ -111,7 +111,7 @@ pub fn base10_to_base7(b10: u16) -> Vec<u8> {
     res
 }
 
-pub fn base10_to_base2(b10: u16, one_based: bool) -> Vec<u8> {
+pub fn base10_to_base2(b10: u32, one_based: bool) -> Vec<u8> {
     let one: u8 = if one_based {1} else {0};
     let mut d = b10 / 2;
     let mut r = b10 % 2;
@@ -124,8 +124,8 @@ pub fn base10_to_base2(b10: u16, one_based: bool) -> Vec<u8> {
         d /= 2;
     }
 
-    for i in 0..tmp.len() {
-        if tmp[i] > 0 {
+    for (i, &bit) in tmp.iter().enumerate() {
+        if bit > 0 {
             res.push(i as u8 + one);
         }
     }
@@ -148,16 +148,3 @@ pub fn initcap(data: String) -> String {
     }
     result
 }
-
-pub fn factor(input :u32) -> Vec<u32>{
-    let count = 2;
-    let mut vector: Vec<u32> = Vec::new();
-
-    for count in count..input {
-        if input % count == 0 {
-            vector.push(count);
-        }
-    }
-
-    return vector;
-}
\ No newline at end of file
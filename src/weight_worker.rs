@@ -5,41 +5,66 @@ use std::io::{BufReader, BufWriter, ErrorKind, Read, Write};
 use std::sync::mpsc::{channel, Sender};
 use std::time::{Duration, Instant};
 use chrono::Local;
-use rayon::iter::{IntoParallelIterator, ParallelIterator};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use rayon::iter::{IndexedParallelIterator, IntoParallelIterator, IntoParallelRefIterator, ParallelIterator};
 use rayon::ThreadPoolBuilder;
 use crate::score_box::rules::{Hand};
-use crate::utils::{available_threads, base10_to_base2, base3_to_base10, factor, records_in_file};
+use crate::score_box::ruleset::{Ruleset, MAX_BOXES};
+use crate::score_box::score_card::ScoreCard;
+use crate::utils::{available_threads, base10_to_base2, base3_to_base10, records_in_file};
 use crate::dices::Dices;
 use crate::EXPORT_DIR;
 use crate::hand_worker::load_hands;
-use crate::play_worker::throw_hand;
+use crate::play_worker::PlayPolicy;
+use crate::optimiser::{Optimiser, Search};
+
+/// Normal quantile of the two sided 95% confidence interval
+const Z_95: f64 = 1.96;
+
+/// Size of a weights file record besides the weights, in files written before u64 totals, sums
+/// of squares and the bonus rule in the header were kept
+const LEGACY_RECORD_SIZE: usize = 20;
+
+/// Size of a weights file record besides the weights
+const RECORD_SIZE: usize = 40;
+
+/// Size of the weights file header, the record count, the bonus rule and the generation
+const HEADER_SIZE: u64 = 18;
+
+/// Size of the weights file header in legacy files, the record count and the generation
+const LEGACY_HEADER_SIZE: u64 = 12;
 
 pub struct RunResult {
-    total_score: u32,
-    total_bonus: u32,
+    total_score: u64,
+    total_bonus: u64,
+    /// Sum of the squared game scores including bonus, NaN when not known (older weights files)
+    total_sq: f64,
     avg_score: f32,
-    pub weights: [f32;15],
-    laps: u32,
+    pub weights: Vec<f32>,
+    laps: u64,
     used_bonus: u32,
     generation: u32,
 }
 
 impl RunResult {
-    fn new() -> RunResult {
+    fn new(n_weights: usize) -> RunResult {
         RunResult {
             total_score: 0,
             total_bonus: 0,
+            total_sq: 0.0,
             avg_score: 0.0,
-            weights: [0.5f32;15],
+            weights: vec![0.5f32; n_weights],
             laps: 0,
             used_bonus: 0,
             generation: 0,
         }
     }
-    fn from(total_score: u32, total_bonus: u32, laps: u32, used_bonus: u32, weights: [f32;15]) -> RunResult {
+    fn from(total_score: u64, total_bonus: u64, total_sq: f64, laps: u64, used_bonus: u32, weights: Vec<f32>) -> RunResult {
         RunResult {
             total_score,
             total_bonus,
+            total_sq,
             avg_score: (total_score + total_bonus) as f32 / laps as f32,
             weights,
             laps,
@@ -47,103 +72,261 @@ impl RunResult {
             generation: 0,
         }
     }
-    fn true_avg_score(&self) -> f32 {
+    /// Average score with the actual bonus of the game rather than the bonus used while learning
+    fn true_avg_score(&self, actual_bonus: u32) -> f32 {
         if self.laps > 0 {
-            (self.total_score + self.total_bonus / self.used_bonus * ACTUAL_BONUS) as f32 / self.laps as f32
+            let bonus_games = self.total_bonus.checked_div(self.used_bonus as u64).unwrap_or(0);
+            (self.total_score + bonus_games * actual_bonus as u64) as f32 / self.laps as f32
         } else {
             0.0
         }
     }
+
+    /// Standard error of the average score, NaN when the variance is not known
+    fn std_error(&self) -> f64 {
+        // The variance is clamped below, which would turn a missing sum of squares into zero
+        if self.laps < 2 || self.total_sq.is_nan() {
+            return f64::NAN;
+        }
+
+        let n = self.laps as f64;
+        let mean = (self.total_score + self.total_bonus) as f64 / n;
+        let variance = ((self.total_sq - n * mean * mean) / (n - 1.0)).max(0.0);
+        (variance / n).sqrt()
+    }
+
+    /// 95% confidence interval of the average score
+    fn confidence_interval(&self) -> (f64, f64) {
+        let margin = Z_95 * self.std_error();
+        (self.avg_score as f64 - margin, self.avg_score as f64 + margin)
+    }
+
+    /// Pools the games of another evaluation of the same weights, keeping the latest generation
+    fn pool(&mut self, other: &RunResult) {
+        self.total_score += other.total_score;
+        self.total_bonus += other.total_bonus;
+        self.total_sq += other.total_sq;
+        self.laps += other.laps;
+        self.avg_score = (self.total_score + self.total_bonus) as f32 / self.laps as f32;
+        self.generation = self.generation.max(other.generation);
+    }
+
+    /// Lower bound of the 95% confidence interval, minus infinity when the variance is not known
+    /// so unmeasured weights rank below all measured ones
+    fn lower_bound(&self) -> f64 {
+        let (low, _) = self.confidence_interval();
+        if low.is_nan() {f64::NEG_INFINITY} else {low}
+    }
 }
 
-const ACTUAL_BONUS: u32 = 50u32;
 const TUNING_LAPS: u32 = 1000000;
 
-pub fn strategy_learn(path: &str, laps: Vec<i64>, use_bonus: Option<u32>) -> Result<(), String> {
+/// Fewest games a candidate plays in the first round of a race
+const RACING_MIN_LAPS: u32 = 10;
+
+/// Best weights so far played again every generation, building up their games when pooled
+const REPLAYED: usize = 4;
+
+#[allow(clippy::too_many_arguments)]
+pub fn strategy_learn(path: &str, laps: Vec<i64>, use_bonus: Option<u32>, optimiser: Optimiser, paired: bool, racing: bool, lower_bound: bool, policy: &PlayPolicy, rules: &Ruleset, seed: Option<u64>) -> Result<(), String> {
+    if rules.forced_order() {
+        return Err(String::from("Error, there are no hand choices to learn weights for in forced order mode"));
+    }
+    if racing && optimiser != Optimiser::Grid {
+        return Err(String::from("Error, racing is only supported by the grid optimiser"));
+    }
     ThreadPoolBuilder::new().num_threads(available_threads() - 1).build_global().unwrap();
-    let bonus = use_bonus.map_or(ACTUAL_BONUS, |b| b);
+    let bonus = use_bonus.unwrap_or(rules.bonus());
 
-    let (mut generation, mut res_vec) = load_weights(path, Some(bonus))?
-        .map_or((0u32, Vec::from([RunResult::new()])),|r| r);
+    let (mut generation, mut res_vec) = load_weights(path, Some(policy.weights_suffix(bonus)), policy.n_weights(rules), rules.bonus_threshold(), bonus)?
+        .map_or((0u32, Vec::from([RunResult::new(policy.n_weights(rules))])),|r| r);
     println!("Loaded {} rows of weights, best average score: {:5.2}",
-             res_vec.len(), res_vec[0].true_avg_score());
+             res_vec.len(), res_vec[0].true_avg_score(rules.bonus()));
+
+    let mut search = Search::new(optimiser, &res_vec[0].weights);
+    let mut rng = seed.map_or_else(StdRng::from_entropy, StdRng::seed_from_u64);
 
     for lap in 0..laps[0] {
         println!("Starting lap {} at {}", lap + 1, Local::now().format("%T"));
-        let weights = res_vec[0].weights;
         generation += 1;
 
-        let base = base3_to_base10(&Vec::from([2u8;15])) + 1;
-        let factor = factor(base)
-            .into_iter().filter(|&v| v >= 15)
-            .next()
-            .map_or(Err(String::from("no factors")), |f| Ok(f))?;
-        let batch = base / factor;
-
-        println!("Running {} batches of size {}", factor, batch);
-        let (sender, receiver) = channel::<RunResult>();
-        (0..factor).into_par_iter().for_each_with(sender, |s, f| {
-            super_run(path, laps[1] as u32, f, batch, weights, bonus, s);
-        });
-
-        for mut res in receiver {
+        let results = match &mut search {
+            None => grid_generation(path, laps[1] as u32, generation, &res_vec[0].weights, paired, racing, bonus, policy, rules, seed)?,
+            Some(search) => {
+                let pool = res_vec.iter().map(|r| r.weights.as_slice()).collect::<Vec<&[f32]>>();
+                let candidates = search.ask(&pool, &mut rng);
+                search_generation(path, laps[1] as u32, generation, candidates, search, paired, bonus, policy, rules, seed)?
+            }
+        };
+        // Replays of the best weights so far, pooled into their rows below
+        let replays = replay_generation(path, laps[1] as u32, generation, &res_vec, bonus, policy, rules, seed)?;
+        for mut res in results.into_iter().chain(replays) {
             res.generation = generation;
             res_vec.push(res);
         }
 
         res_vec.retain(|r| r.laps > 0);
-        res_vec.sort_by(|a, b| a.avg_score.total_cmp(&b.avg_score));
+        if optimiser == Optimiser::Grid {
+            res_vec.iter_mut().for_each(|r| r.weights = trim_weights(&r.weights));
+        }
+        res_vec = pool_duplicates(res_vec);
+
+        // Ranked on the lower confidence bound favours weights whose lead is not just luck, weights
+        // from older files without a known variance rank last by their average
+        if lower_bound {
+            res_vec.sort_by(|a, b| a.lower_bound().total_cmp(&b.lower_bound()).then(a.avg_score.total_cmp(&b.avg_score)));
+        } else {
+            res_vec.sort_by(|a, b| a.avg_score.total_cmp(&b.avg_score));
+        }
         res_vec.reverse();
         res_vec.truncate(1000);
-        res_vec.iter_mut().for_each(|r| r.weights = trim_weights(r.weights));
 
-        println!("Best average score: {:5.2}", res_vec[0].true_avg_score());
-        println!("Worst average score: {:5.2}", res_vec[res_vec.len() - 1].true_avg_score());
+        println!("Best average score: {:5.2}", res_vec[0].true_avg_score(rules.bonus()));
+        println!("Worst average score: {:5.2}", res_vec[res_vec.len() - 1].true_avg_score(rules.bonus()));
         println!("lap {} ended at {}", lap + 1, Local::now().format("%T"));
 
-        save_weights(path, Some(bonus), generation, &res_vec)?;
+        save_weights(path, Some(policy.weights_suffix(bonus)), rules.bonus_threshold(), bonus, generation, &res_vec)?;
     }
 
     Ok(())
 }
 
-fn super_run(path: &str, sub_laps: u32, factor: u32, batch: u32, weights: [f32;15], bonus: u32, sender: &mut Sender<RunResult>) {
+/// Tries every combination of -0.1, 0 and +0.1 around the best weights, split in batches each
+/// giving its best result. Paired, all combinations of a batch play the same dices. Racing, the
+/// combinations of a batch are raced by successive halving instead of all playing the sub laps.
+#[allow(clippy::too_many_arguments)]
+fn grid_generation(path: &str, sub_laps: u32, generation: u32, weights: &[f32], paired: bool, racing: bool, bonus: u32, policy: &PlayPolicy, rules: &Ruleset, seed: Option<u64>) -> Result<Vec<RunResult>, String> {
+    let n_hands = rules.n_hands();
+    if n_hands > MAX_BOXES {
+        return Err(format!("Error, the grid optimiser supports rulesets with up to {} hands", MAX_BOXES));
+    }
 
-    let mut dices = Dices::new();
-    let mut best_results = RunResult::new();
+    // Phased weights are tuned one phase at a time, taking turns between generations
+    let phase = (generation - 1) as usize % policy.n_phases();
+    if policy.n_phases() > 1 {
+        println!("Tuning weights for phase {}", policy.phase_name(phase));
+    }
 
-    let res = load_hands(path, true);
+    let base = base3_to_base10(&vec![2u8; n_hands]) + 1;
+    let factor = (15..base).find(|&v| base.is_multiple_of(v)).ok_or(String::from("no factors"))?;
+    let batch = base / factor;
+
+    println!("Running {} batches of size {}", factor, batch);
+    let (sender, receiver) = channel::<(u32, RunResult)>();
+    (0..factor).into_par_iter().for_each_with(sender, |s, f| {
+        let stream = ((generation as u64) << 32) | f as u64;
+        let dices = if paired {
+            Dices::paired(rules.n_dices(), rules.dice_model(), seed, stream)
+        } else {
+            Dices::from_seed(rules.n_dices(), rules.dice_model(), seed, stream)
+        };
+        super_run(path, sub_laps, f, batch, weights, phase, racing, bonus, policy, rules, dices, s);
+    });
+
+    // Results arrive in completion order, sort on batch to keep seeded runs reproducible
+    let mut received = receiver.into_iter().collect::<Vec<(u32, RunResult)>>();
+    received.sort_by_key(|(f, _)| *f);
+    Ok(received.into_iter().map(|(_, res)| res).collect())
+}
+
+/// Plays the candidates of an optimiser generation in parallel and tells the optimiser their
+/// scores. Paired, all candidates play the same dices. Only the best candidate is kept, played
+/// again over more games on fresh dices, as the short runs of the others would rank as outliers.
+#[allow(clippy::too_many_arguments)]
+fn search_generation(path: &str, sub_laps: u32, generation: u32, candidates: Vec<Vec<f32>>, search: &mut Search, paired: bool, bonus: u32, policy: &PlayPolicy, rules: &Ruleset, seed: Option<u64>) -> Result<Vec<RunResult>, String> {
+    let hands = load_hands(path, rules, true)?;
+    let stream = |c: usize| ((generation as u64) << 32) | c as u64;
+    let shared = paired.then(|| Dices::paired(rules.n_dices(), rules.dice_model(), seed, stream(0)));
+
+    println!("Running {} candidates of {} games", candidates.len(), sub_laps);
+    let results = candidates
+        .par_iter()
+        .enumerate()
+        .map(|(c, weights)| {
+            let mut dices = shared
+                .as_ref()
+                .map_or_else(|| Dices::from_seed(rules.n_dices(), rules.dice_model(), seed, stream(c)), Dices::clone);
+            run(sub_laps, &mut dices, &hands, weights, bonus, policy, rules)
+        })
+        .collect::<Result<Vec<RunResult>, String>>()?;
+    search.tell(&candidates, &results.iter().map(|r| r.avg_score).collect::<Vec<f32>>());
+
+    let best = (0..results.len()).max_by(|&a, &b| results[a].avg_score.total_cmp(&results[b].avg_score));
+    match best {
+        Some(best) => {
+            let mut dices = Dices::from_seed(rules.n_dices(), rules.dice_model(), seed, stream(candidates.len()));
+            Ok(vec![run(TUNING_LAPS, &mut dices, &hands, &candidates[best], bonus, policy, rules)?])
+        },
+        None => Ok(Vec::new()),
+    }
+}
+
+/// Plays the best weights so far again over the sub laps on fresh dices, so that the weights
+/// surviving over generations keep gaining games instead of standing on their first evaluation
+#[allow(clippy::too_many_arguments)]
+fn replay_generation(path: &str, sub_laps: u32, generation: u32, res_vec: &[RunResult], bonus: u32, policy: &PlayPolicy, rules: &Ruleset, seed: Option<u64>) -> Result<Vec<RunResult>, String> {
+    let hands = load_hands(path, rules, true)?;
+    // The starting weights of a new file have no games yet, replayed they would be a short run
+    let best = res_vec.iter().filter(|rr| rr.laps > 0).take(REPLAYED).collect::<Vec<&RunResult>>();
+
+    println!("Replaying the {} best weights for {} games", best.len(), sub_laps);
+    best.par_iter()
+        .enumerate()
+        .map(|(i, rr)| {
+            // Streams counted down from the top keep clear of the batch and candidate streams
+            let stream = ((generation as u64) << 32) | (u32::MAX - i as u32) as u64;
+            let mut dices = Dices::from_seed(rules.n_dices(), rules.dice_model(), seed, stream);
+            run(sub_laps, &mut dices, &hands, &rr.weights, bonus, policy, rules)
+        })
+        .collect()
+}
+
+#[allow(clippy::too_many_arguments)]
+fn super_run(path: &str, sub_laps: u32, factor: u32, batch: u32, weights: &[f32], phase: usize, racing: bool, bonus: u32, policy: &PlayPolicy, rules: &Ruleset, mut dices: Dices, sender: &mut Sender<(u32, RunResult)>) {
+
+    let res = load_hands(path, rules, true);
 
     match res {
         Ok(hands) => {
-            let mut tuned_weights = [0f32;15];
+            let offset = phase * rules.n_hands();
+            let tuned_weights = |f: u32| {
+                let mut tuning = vec![0f32; rules.n_hands()];
+                base10_to_tuning(f, &mut tuning);
+
+                let mut tuned = weights.to_vec();
+                tuning.iter().enumerate().for_each(|(i, &t)| tuned[offset + i] = t + weights[offset + i]);
+                tuned
+            };
+            let mut play = |f: u32, laps: u32| {
+                dices.replay();
+                run(laps, &mut dices, &hands, &tuned_weights(f), bonus, policy, rules)
+            };
+
             let start = factor * batch;
             let end = start + batch;
             println!("Starting factor batch {:8} to {:8}", start, end);
             let begin = Instant::now();
-            for f in start..end {
-                let mut tuning = [0f32;15];
-                base10_to_tuning(f, &mut tuning);
-
-                weights.iter().enumerate().for_each(|(i, &w)| tuned_weights[i] = tuning[i] + w);
-
-                match run(sub_laps, &mut dices, &hands, tuned_weights, bonus) {
-                    Ok(rr) => {
-                        if rr.avg_score > best_results.avg_score {
-                            best_results =rr;
-                        }
-                    },
-                    Err(e) => {
-                        println!("...error in super batch {:05}: {}", batch, e);
-                        return;
-                    }
+            let best = if racing {
+                race((start..end).collect(), sub_laps, play)
+            } else {
+                (start..end)
+                    .map(|f| play(f, sub_laps).map(|rr| (f, rr.avg_score)))
+                    .try_fold((start, f32::MIN), |best, res| res.map(|r| if r.1 > best.1 {r} else {best}))
+                    .map(|(f, _)| f)
+            };
+            let best = match best {
+                Ok(f) => f,
+                Err(e) => {
+                    println!("...error in super batch {:05}: {}", batch, e);
+                    return;
                 }
-            }
+            };
 
             // Tune result to ensure the super run result isn't an outlier
-            match run(TUNING_LAPS, &mut dices, &hands, best_results.weights, bonus) {
+            dices.unpair();
+            match run(TUNING_LAPS, &mut dices, &hands, &tuned_weights(best), bonus, policy, rules) {
                 Ok(rr) => {
-                    sender.send(rr).unwrap();
+                    sender.send((factor, rr)).unwrap();
                 },
                 Err(e) => {
                     println!("...error in super batch {:05}: {}", batch, e);
@@ -162,54 +345,94 @@ fn super_run(path: &str, sub_laps: u32, factor: u32, batch: u32, weights: [f32;1
 
 }
 
-fn run(laps: u32, dices: &mut Dices, hands: &Vec<Box<Hand>>, weights: [f32;15], bonus: u32) -> Result<RunResult, String> {
-    let mut available_hands: u16;
-    let mut mc_scores: HashMap<u8, u16> = HashMap::new();
-    let mut total_score = 0u32;
-    let mut total_bonus = 0u32;
+/// Races candidates by successive halving, every round all candidates left play the same number
+/// of games and the worse half is dropped, with the games doubled for the next round. The first
+/// round is sized so that the last two candidates play the given laps, but no fewer than
+/// RACING_MIN_LAPS, and no round plays more than the given laps. Returns the winner.
+fn race<F>(candidates: Vec<u32>, laps: u32, mut play: F) -> Result<u32, String>
+where F: FnMut(u32, u32) -> Result<RunResult, String>
+{
+    let rounds = usize::BITS - candidates.len().saturating_sub(1).leading_zeros();
+    let mut round_laps = laps.checked_shr(rounds.saturating_sub(1)).unwrap_or(0).max(RACING_MIN_LAPS).min(laps);
+    let mut field = candidates;
+
+    while field.len() > 1 {
+        let mut scored = field
+            .into_iter()
+            .map(|c| play(c, round_laps).map(|rr| (c, rr.avg_score)))
+            .collect::<Result<Vec<(u32, f32)>, String>>()?;
+        scored.sort_by(|a, b| b.1.total_cmp(&a.1));
+        scored.truncate(scored.len().div_ceil(2));
+
+        field = scored.into_iter().map(|(c, _)| c).collect();
+        round_laps = round_laps.saturating_mul(2).min(laps);
+    }
 
-    for _ in 0..laps {
-        // For each lap, start with a blank score card and on the top of the hash tree
-        available_hands = 32767;
-        mc_scores.clear();
+    field.first().copied().ok_or(String::from("Error, no candidates to race"))
+}
+
+pub fn simulate_weights(path: &str, laps: u32, bonus: u32, policy: &PlayPolicy, rules: &Ruleset, seed: Option<u64>) -> Result<(), String> {
+    let hands = load_hands(path, rules, true)?;
+    let weights = match load_weights(path, Some(policy.weights_suffix(bonus)), policy.n_weights(rules), rules.bonus_threshold(), bonus)? {
+        // Weights make no difference when there is only one hand to choose from
+        _ if rules.forced_order() => vec![1f32; policy.n_weights(rules)],
+        Some((_, mut w)) if !w.is_empty() => w.swap_remove(0).weights,
+        Some(_) => return Err(String::from("Error, no weights in weights file")),
+        None => return Err(String::from("Error, no weights file available")),
+    };
+    let mut dices = Dices::from_seed(rules.n_dices(), rules.dice_model(), seed, 0);
+
+    let rr = run(laps, &mut dices, &hands, &weights, bonus, policy, rules)?;
+    let margin = Z_95 * rr.std_error();
+    println!("Weights strategy average score over {} games: {:6.2} ± {:.2} (95%)", laps, rr.true_avg_score(rules.bonus()), margin);
+
+    Ok(())
+}
 
-        // Run through all 15 available hands in random order
-        while available_hands > 0 {
-            let thrown = throw_hand(dices, available_hands, &hands)?;
-            let hand_score = best_available_game_hand(&thrown, available_hands, &hands, weights)?;
+fn run(laps: u32, dices: &mut Dices, hands: &[Hand], weights: &[f32], bonus: u32, policy: &PlayPolicy, rules: &Ruleset) -> Result<RunResult, String> {
+    let mut total_score = 0u64;
+    let mut total_bonus = 0u64;
+    let mut total_sq = 0f64;
 
-            mc_scores.insert(hand_score.0, hand_score.1);
-            available_hands -= 2u16.pow(hand_score.0 as u32);
+    for _ in 0..laps {
+        // For each lap, start with a blank score card and on the top of the hash tree
+        let mut score_card = ScoreCard::new(rules);
+
+        // Run through all available hands in random order
+        while score_card.available_hands() > 0 {
+            dices.next_turn();
+            let (thrown, rolls_used) = policy.throw_hand(dices, &score_card, hands, weights, bonus, rules)?;
+            let (hand, _) = best_available_game_hand(&thrown, &score_card, rules, weights, bonus, policy)?;
+            score_card.score(rules, hand, &thrown);
+            score_card.save_rolls(rules, rolls_used);
         }
 
-        // Calculate if we are eligible for the bonus of 50 points
-        let bonus = if mc_scores
-            .iter()
-            .filter(|&(&h, _)| h < 6)
-            .map(|(_, &s)| s)
-            .sum::<u16>() >= 63 { bonus } else {0u32};
-        let score = mc_scores.iter().map(|(_, &s)| s).sum::<u16>() as u32;
-        total_score += score;
-        total_bonus += bonus;
+        // Calculate if we are eligible for the bonus
+        let game_bonus = score_card.bonus(rules, bonus);
+        total_score += score_card.total() as u64;
+        total_bonus += game_bonus as u64;
+        total_sq += ((score_card.total() as u32 + game_bonus) as f64).powi(2);
     }
 
-    Ok(RunResult::from(total_score, total_bonus, laps, bonus, weights))
+    Ok(RunResult::from(total_score, total_bonus, total_sq, laps as u64, bonus, weights.to_vec()))
 }
 
-pub fn best_available_game_hand(thrown: &Vec<u8>, available_hands: u16, hands: &Vec<Box<Hand>>, weights: [f32;15]) -> Result<(u8, u16), String> {
+pub fn best_available_game_hand(thrown: &[u8], score_card: &ScoreCard, rules: &Ruleset, weights: &[f32], bonus: u32, policy: &PlayPolicy) -> Result<(u8, u16), String> {
+    let available_hands = score_card.available_hands();
+    let weights = policy.phase_weights(weights, score_card, rules);
     let mut best_hand_score: Option<(u8, u16)> = None;
-    let mut weighted_score: f32;
-    let mut max_weighted_score: f32 = f32::MIN;
-    let mut score: f32;
-
-    // Try to find hand which gives the best weighted score
-    for hand in base10_to_base2(available_hands, false) {
-        score = hands[hand as usize].score(thrown);
-        weighted_score = score * weights[hand as usize];
-
-        if weighted_score > max_weighted_score {
-            max_weighted_score = weighted_score;
-            best_hand_score = Some((hand, score as u16));
+    let mut hand_value: f32;
+    let mut max_hand_value: f32 = f32::MIN;
+    let mut score: u16;
+
+    // Try to find hand which gives the best value, a Yahtzee may be restricted by the joker rules
+    for hand in base10_to_base2(rules.allowed_hands(thrown, available_hands), false) {
+        score = rules.score(hand, thrown, available_hands);
+        hand_value = policy.hand_value(hand, score, weights, score_card, bonus);
+
+        if hand_value > max_hand_value {
+            max_hand_value = hand_value;
+            best_hand_score = Some((hand, score));
         }
     }
 
@@ -227,7 +450,7 @@ pub fn key_to_hand_score(key: u16) -> (u16, u16) {
     (hand, score)
 }
 
-fn save_weights<A>(path: &str, suffix: Option<A>, generation: u32, weights_score: &Vec<RunResult>) -> Result<(), String>
+fn save_weights<A>(path: &str, suffix: Option<A>, bonus_threshold: u16, bonus: u32, generation: u32, weights_score: &[RunResult]) -> Result<(), String>
 where A: Display
 {
     let sfx = suffix.map_or(String::new(), |s| format!(".{}", s));
@@ -243,36 +466,49 @@ where A: Display
         format!("Error while writing to weights file: {}", e)
     })?;
 
+    // The bonus rule the weights are learned for
+    let mut buf = [0u8; 6];
+    buf[0..2].copy_from_slice(&bonus_threshold.to_le_bytes());
+    buf[2..6].copy_from_slice(&bonus.to_le_bytes());
+    buf_writer.write_all(&buf).map_err(|e| {
+        format!("Error while writing to weights file: {}", e)
+    })?;
+
     let buf: [u8;4] = generation.to_le_bytes();
     buf_writer.write_all(&buf).map_err(|e| {
         format!("Error while writing to weights file: {}", e)
     })?;
 
-    let mut buf = [0u8;80];
-    for i in 0..len as usize {
+    let n_hands = weights_score.first().map_or(0, |w| w.weights.len());
+    let mut buf = vec![0u8; RECORD_SIZE + 4 * n_hands];
+    for rr in weights_score {
         let mut offset = 0;
-        weights_score[i].total_score.to_le_bytes().iter().for_each(|v| {
+        rr.total_score.to_le_bytes().iter().for_each(|v| {
             buf[offset] = *v;
             offset += 1;
         });
-        weights_score[i].total_bonus.to_le_bytes().iter().for_each(|v| {
+        rr.total_bonus.to_le_bytes().iter().for_each(|v| {
             buf[offset] = *v;
             offset += 1;
         });
-        weights_score[i].laps.to_le_bytes().iter().for_each(|v| {
+        rr.laps.to_le_bytes().iter().for_each(|v| {
             buf[offset] = *v;
             offset += 1;
         });
-        weights_score[i].used_bonus.to_le_bytes().iter().for_each(|v| {
+        rr.used_bonus.to_le_bytes().iter().for_each(|v| {
             buf[offset] = *v;
             offset += 1;
         });
-        weights_score[i].generation.to_le_bytes().iter().for_each(|v| {
+        rr.generation.to_le_bytes().iter().for_each(|v| {
+            buf[offset] = *v;
+            offset += 1;
+        });
+        rr.total_sq.to_le_bytes().iter().for_each(|v| {
             buf[offset] = *v;
             offset += 1;
         });
 
-        for weight in weights_score[i].weights {
+        for weight in &rr.weights {
             weight.to_le_bytes().iter().for_each(|v| {
                 buf[offset] = *v;
                 offset += 1;
@@ -289,7 +525,7 @@ where A: Display
     Ok(())
 }
 
-pub fn load_weights<A>(path: &str, suffix: Option<A>) -> Result<Option<(u32, Vec<RunResult>)>, String>
+pub fn load_weights<A>(path: &str, suffix: Option<A>, n_weights: usize, bonus_threshold: u16, bonus: u32) -> Result<Option<(u32, Vec<RunResult>)>, String>
 where A: Display
 {
     let sfx = suffix.map_or(String::new(), |s| format!(".{}", s));
@@ -306,15 +542,37 @@ where A: Display
     };
 
 
+    let file_size = buf_reader.get_ref().metadata().map_err(|e| format!("Error while reading from file {}: {}", path_name, e))?.len();
     let mut n_records = records_in_file(&mut buf_reader, path_name)?;
 
+    // Legacy files have a shorter header and shorter records
+    let legacy = file_size == LEGACY_HEADER_SIZE + n_records * (LEGACY_RECORD_SIZE + 4 * n_weights) as u64;
+    let record_size = if legacy {LEGACY_RECORD_SIZE} else {RECORD_SIZE};
+
+    if !legacy {
+        if file_size != HEADER_SIZE + n_records * (RECORD_SIZE + 4 * n_weights) as u64 {
+            return Err(format!("Error, unexpected size of file {}", path_name));
+        }
+
+        let mut buf = [0u8; 6];
+        buf_reader.read_exact(&mut buf).map_err(|e| {
+            format!("Error while reading from weights file: {}", e)
+        })?;
+        let file_threshold = u16::from_le_bytes(buf[0..2].try_into().unwrap());
+        let file_bonus = u32::from_le_bytes(buf[2..6].try_into().unwrap());
+        if file_threshold != bonus_threshold || file_bonus != bonus {
+            return Err(format!("Error, file {} is learned for a bonus of {} at {}, not {} at {}",
+                               path_name, file_bonus, file_threshold, bonus, bonus_threshold));
+        }
+    }
+
     let mut buf = [0u8;4];
     buf_reader.read_exact(&mut buf).map_err(|e| {
         format!("Error while reading from weights file: {}", e)
     })?;
     let generation = u32::from_le_bytes(buf[0..4].try_into().unwrap());
 
-    let mut buf = [0u8; 80];
+    let mut buf = vec![0u8; record_size + 4 * n_weights];
     let mut res_vec: Vec<RunResult> = Vec::new();
 
     while n_records > 0 {
@@ -322,19 +580,28 @@ where A: Display
             format!("Error while reading from weights file: {}", e)
         })?;
 
-        let total_score = u32::from_le_bytes(buf[0..4].try_into().unwrap());
-        let total_bonus = u32::from_le_bytes(buf[4..8].try_into().unwrap());
-        let laps = u32::from_le_bytes(buf[8..12].try_into().unwrap());
-        let used_bonus = u32::from_le_bytes(buf[12..16].try_into().unwrap());
-        let generation = u32::from_le_bytes(buf[16..20].try_into().unwrap());
-
-        let mut offset: usize = 20;
-        let mut weights = [0f32;15];
-        for o in 0..15usize {
-            weights[o] = f32::from_le_bytes(buf[offset..offset+4].try_into().unwrap());
-            offset += 4;
-        }
-        let mut rr = RunResult::from(total_score, total_bonus, laps, used_bonus, weights);
+        let (total_score, total_bonus, laps, at) = if legacy {
+            (u32::from_le_bytes(buf[0..4].try_into().unwrap()) as u64,
+             u32::from_le_bytes(buf[4..8].try_into().unwrap()) as u64,
+             u32::from_le_bytes(buf[8..12].try_into().unwrap()) as u64, 12)
+        } else {
+            (u64::from_le_bytes(buf[0..8].try_into().unwrap()),
+             u64::from_le_bytes(buf[8..16].try_into().unwrap()),
+             u64::from_le_bytes(buf[16..24].try_into().unwrap()), 24)
+        };
+        let used_bonus = u32::from_le_bytes(buf[at..at + 4].try_into().unwrap());
+        let generation = u32::from_le_bytes(buf[at + 4..at + 8].try_into().unwrap());
+        let total_sq = if legacy {
+            f64::NAN
+        } else {
+            f64::from_le_bytes(buf[at + 8..at + 16].try_into().unwrap())
+        };
+
+        let weights = buf[record_size..]
+            .chunks_exact(4)
+            .map(|w| f32::from_le_bytes(w.try_into().unwrap()))
+            .collect::<Vec<f32>>();
+        let mut rr = RunResult::from(total_score, total_bonus, total_sq, laps, used_bonus, weights);
         rr.generation = generation;
         res_vec.push(rr);
 
@@ -344,7 +611,7 @@ where A: Display
     Ok(Some((generation, res_vec)))
 }
 
-pub fn export_weights<A>(path: &str, suffix: Option<A>, generation: u32, weights: &Vec<RunResult>) -> Result<(), String>
+pub fn export_weights<A>(path: &str, suffix: Option<A>, generation: u32, weights: &Vec<RunResult>, policy: &PlayPolicy, rules: &Ruleset) -> Result<(), String>
 where A: Display
 {
     let sfx = suffix.map_or(String::new(), |s| format!(".{}", s));
@@ -354,34 +621,74 @@ where A: Display
         Err(e) => return Err(format!("Error while open/create file {}: {}", path_name, e)),
     };
 
-    write!(buf_writer, "Generation: {}\n", generation).map_err(|e| e.to_string())?;
-    write!(buf_writer, "avg_score  true_avg  tot_score  tot_bonus  used_bonus  laps     gen  weights\n")
+    writeln!(buf_writer, "Generation: {}", generation).map_err(|e| e.to_string())?;
+    writeln!(buf_writer, "Bonus: {} at {}", rules.bonus(), rules.bonus_threshold()).map_err(|e| e.to_string())?;
+    writeln!(buf_writer, "Dices: {}", rules.dice_model()).map_err(|e| e.to_string())?;
+    writeln!(buf_writer, "avg_score  true_avg  std_err  95% interval        tot_score  tot_bonus  used_bonus  laps     gen  weights")
         .map_err(|e| e.to_string())?;
 
     for weight in weights {
-        write!(buf_writer, "{:0<6.3}    {:0<6.3}  {:7}   {:7}     {:3}         {:5} {:5}    [",
+        let (low, high) = weight.confidence_interval();
+        write!(buf_writer, "{:0<6.3}    {:0<6.3}  {:7.3}  [{:7.2}, {:7.2}]  {:7}   {:7}     {:3}         {:5} {:5}    [",
                weight.avg_score,
-               weight.true_avg_score(), weight.total_score, weight.total_bonus,
+               weight.true_avg_score(rules.bonus()), weight.std_error(), low, high,
+               weight.total_score, weight.total_bonus,
                weight.used_bonus, weight.laps, weight.generation)
             .map_err(|e| e.to_string())?;
         let mut buf: Vec<String> = Vec::new();
-        for w in weight.weights {
+        for w in &weight.weights {
             buf.push(format!("{:0<2.1}", w));
         }
-        write!(buf_writer, "{}]\n", buf.join(", ").to_string()).map_err(|e| e.to_string())?;
+        writeln!(buf_writer, "{}]", buf.join(", ")).map_err(|e| e.to_string())?;
+    }
+
+    // Show how the priorities of the best weights change over the game
+    if let (true, Some(best)) = (policy.n_phases() > 1, weights.first()) {
+        let n_hands = rules.n_hands();
+        writeln!(buf_writer, "\nBest weights by phase").map_err(|e| e.to_string())?;
+        write!(buf_writer, "{:16}", "hand").map_err(|e| e.to_string())?;
+        for phase in 0..policy.n_phases() {
+            write!(buf_writer, "  {:>21}", policy.phase_name(phase)).map_err(|e| e.to_string())?;
+        }
+        writeln!(buf_writer).map_err(|e| e.to_string())?;
+
+        for hand in rules.hands() {
+            write!(buf_writer, "{:16}", hand.name()).map_err(|e| e.to_string())?;
+            for phase in 0..policy.n_phases() {
+                write!(buf_writer, "  {:>21.1}", best.weights[phase * n_hands + hand.id()]).map_err(|e| e.to_string())?;
+            }
+            writeln!(buf_writer).map_err(|e| e.to_string())?;
+        }
     }
 
     Ok(())
 }
 
-fn trim_weights(weights: [f32;15]) -> [f32;15] {
-    let mut res = [0f32;15];
-    weights.iter().enumerate().for_each(|(i, &w)| res[i] = (w * 10.0).round() / 10.0);
+/// Pools the evaluations of identical weights into one row, so that weights kept over generations
+/// build up their games rather than the luckiest evaluation standing out
+fn pool_duplicates(res_vec: Vec<RunResult>) -> Vec<RunResult> {
+    let mut index: HashMap<Vec<u32>, usize> = HashMap::new();
+    let mut pooled: Vec<RunResult> = Vec::with_capacity(res_vec.len());
+
+    for rr in res_vec {
+        let key = rr.weights.iter().map(|w| w.to_bits()).collect::<Vec<u32>>();
+        match index.get(&key) {
+            Some(&i) => pooled[i].pool(&rr),
+            None => {
+                index.insert(key, pooled.len());
+                pooled.push(rr);
+            }
+        }
+    }
+
+    pooled
+}
 
-    res
+fn trim_weights(weights: &[f32]) -> Vec<f32> {
+    weights.iter().map(|&w| (w * 10.0).round() / 10.0).collect()
 }
 
-fn base10_to_tuning(b10: u32, into: &mut [f32;15]) {
+fn base10_to_tuning(b10: u32, into: &mut [f32]) {
     let mut d = b10 / 3;
     let mut r = b10 % 3;
 
@@ -413,4 +720,25 @@ impl DurationDisplay for Duration {
 
         format!("D:{:02} H:{:02} M:{:02} S:{:02}", d, h, m, s)
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pools_weights_seen_in_two_generations() {
+        let mut first = RunResult::from(18000, 500, 3.5e6, 100, 50, trim_weights(&[0.1 + 0.2, 0.4]));
+        first.generation = 1;
+        let other = RunResult::from(19000, 0, 3.7e6, 100, 50, vec![0.6, 0.4]);
+        let mut again = RunResult::from(37000, 1000, 7.3e6, 200, 50, trim_weights(&[0.3, 0.4]));
+        again.generation = 2;
+
+        let pooled = pool_duplicates(vec![first, other, again]);
+        assert_eq!(pooled.len(), 2);
+        assert_eq!(pooled[0].laps, 300);
+        assert_eq!(pooled[0].total_score, 55000);
+        assert_eq!(pooled[0].total_bonus, 1500);
+        assert_eq!(pooled[0].generation, 2);
+        assert_eq!(pooled[1].laps, 100);
+    }
+}